@@ -11,6 +11,7 @@
 #![allow(clippy::module_name_repetitions)]
 use log::info;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[path = "./platform/mod.rs"]
 pub mod platform;
@@ -27,7 +28,20 @@ pub mod model;
 use gfx::Renderer as RendererApi;
 use platform::{audio::PlatformApi as AudioApi, Platform, PlatformApi};
 
-use crate::platform::audio::NoteInput;
+use crate::{
+    model::{
+        highscores::{Highscore, HighscoreKey, Highscores},
+        performance::{Performance, PlayerResult},
+        scoring::{metrics::Metrics, PlayerOptions},
+        timing::SongOffsets,
+        Score, Song,
+    },
+    platform::audio::{
+        players::{PlayerInputs, Resolved},
+        recovery::{Event as InputEvent, Supervisor},
+        Detection, NoteInput,
+    },
+};
 
 pub trait SettingsTrait: Default + Serialize + DeserializeOwned {}
 
@@ -69,9 +83,295 @@ pub struct UserData {
     library: model::library::Settings,
     #[serde(default)]
     audio: <Audio as AudioApi>::InitSettings,
+    #[serde(default)]
+    song_offsets: model::timing::SongOffsets,
+    /// Scoring options of each player slot
+    #[serde(default)]
+    player_options: Vec<PlayerOptions>,
+    #[serde(default)]
+    stats: model::statistics::UserStats,
+    /// Input of each player slot
+    #[serde(default)]
+    inputs: PlayerInputs<<Audio as AudioApi>::NoteInputId>,
+    /// Input for player slots whose own input is missing, the default input if `None`
+    #[serde(default)]
+    fallback_input: Option<<Audio as AudioApi>::NoteInputId>,
 }
 impl SettingsTrait for UserData {}
 
+/// Check all songs found in `dirs` and print their problems
+///
+/// Returns whether the songs are free of errors.
+#[must_use]
+pub fn lint(dirs: Vec<PathBuf>) -> bool {
+    let library = model::Library::init_song_dirs(&model::library::Settings::with_song_dirs(dirs));
+    let mut ok = true;
+    for song in library.iter() {
+        for diagnostic in song.lint_diagnostics() {
+            println!("{}: {}", song.loader_key(), diagnostic);
+            ok &= diagnostic.severity() != model::lint::Severity::Error;
+        }
+    }
+    ok
+}
+
+/// Convert a MIDI or `.kar` file to the contents of a `.txt` song, given the arguments
+/// `<file> [--track N] [--pitch-offset K]`
+///
+/// # Errors
+///
+/// If the arguments are invalid, or the file cannot be read or imported
+pub fn convert(args: Vec<String>) -> anyhow::Result<String> {
+    let mut path = None;
+    let mut options = model::midi::ImportOptions::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--track" => options.melody_track = Some(value()?.parse()?),
+            "--pitch-offset" => options.pitch_offset = value()?.parse()?,
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => anyhow::bail!("Unexpected argument {}", arg),
+        }
+    }
+    let path = path.ok_or_else(|| anyhow::anyhow!("Missing file to convert"))?;
+    model::midi::convert_to_txt(&std::fs::read(path)?, &options)
+}
+
+/// Open the saved input of each player slot, or just the default input if none are saved.
+/// Returns warnings for the user about missing inputs.
+fn supervise_note_inputs(
+    audio: &Audio,
+    inputs: &PlayerInputs<<Audio as AudioApi>::NoteInputId>,
+    fallback: Option<<Audio as AudioApi>::NoteInputId>,
+) -> (Supervisor<Audio>, Vec<String>) {
+    let mut inputs = inputs.clone();
+    if inputs.is_empty() {
+        inputs.set(0, audio.default_note_input_id());
+    }
+    let resolved = inputs.resolve(&audio.list_note_inputs(), fallback.as_ref());
+    let warnings: Vec<_> = resolved.iter().filter_map(Resolved::warning).collect();
+    for warning in &warnings {
+        log::warn!("{}", warning);
+    }
+    (Supervisor::new(audio, &resolved, fallback), warnings)
+}
+
+/// Give player `slot` the input at index `choice` of the `available` ones shown, or none, and
+/// save the choice
+fn choose_input(
+    available: &[<Audio as AudioApi>::NoteInputId],
+    note_inputs: &mut Supervisor<Audio>,
+    inputs: &mut PlayerInputs<<Audio as AudioApi>::NoteInputId>,
+    slot: usize,
+    choice: Option<usize>,
+) {
+    let id = choice.and_then(|idx| available.get(idx).cloned());
+    inputs.set(slot, id.clone());
+    note_inputs.assign(slot, id);
+}
+
+/// The `available` inputs, and the inputs and `options` of the player slots for the UI
+fn input_choices(
+    available: &[<Audio as AudioApi>::NoteInputId],
+    note_inputs: &Supervisor<Audio>,
+    inputs: &PlayerInputs<<Audio as AudioApi>::NoteInputId>,
+    options: &[PlayerOptions],
+) -> ui::Inputs {
+    let slots = 0..note_inputs.len().max(inputs.len());
+    ui::Inputs {
+        available: available.iter().map(ToString::to_string).collect(),
+        chosen: slots
+            .clone()
+            .map(|slot| inputs.get(slot).map(ToString::to_string))
+            .collect(),
+        active: slots
+            .clone()
+            .map(|slot| note_inputs.active(slot).map(ToString::to_string))
+            .collect(),
+        options: slots
+            .map(|slot| options.get(slot).cloned().unwrap_or_default())
+            .collect(),
+    }
+}
+
+/// The options of player `slot`, adding default options for the slots before it as needed
+fn player_options(options: &mut Vec<PlayerOptions>, slot: usize) -> &mut PlayerOptions {
+    if options.len() <= slot {
+        options.resize(slot + 1, PlayerOptions::default());
+    }
+    &mut options[slot]
+}
+
+/// Read all note inputs, reopening those whose device failed or was unplugged. Returns the
+/// detections of each player slot.
+fn read_note_inputs(audio: &Audio, note_inputs: &mut Supervisor<Audio>) -> Vec<Option<Detection>> {
+    note_inputs.poll(audio, audio.now());
+    let detections = (0..note_inputs.len())
+        .map(|slot| note_inputs.read(slot, NoteInput::read_current))
+        .collect();
+    for event in note_inputs.take_events() {
+        match event {
+            InputEvent::Lost { .. } | InputEvent::OpenFailed { .. } => {
+                log::warn!("{:?}", event);
+            }
+            _ => info!("{:?}", event),
+        }
+    }
+    detections
+}
+
+/// What the game is doing
+enum Stage {
+    ChoosingSong,
+    Singing(Performance),
+    /// The score and metrics of each player slot
+    Results(Vec<(Score, Metrics)>),
+    ChoosingInputs,
+}
+impl Stage {
+    /// Follow the user's `action` at `now`, with `players` player slots. Songs are sung with
+    /// the user's offsets, and corrections are saved for the next time.
+    fn apply(
+        &mut self,
+        action: ui::Action,
+        library: &model::Library,
+        song_offsets: &mut SongOffsets,
+        options: &[PlayerOptions],
+        now: f64,
+        players: usize,
+    ) {
+        match (action, self) {
+            (ui::Action::Sing(idx), stage) => match library.load(&library[idx]) {
+                Ok(song) => {
+                    let offsets = song_offsets.get(&song.id());
+                    *stage = Self::Singing(Performance::new(song, offsets, options, now, players));
+                }
+                Err(err) => log::error!("Failed to load {}: {}", library[idx].name(), err),
+            },
+            (ui::Action::SetOffsets(offsets), Self::Singing(performance)) => {
+                performance.set_offsets(offsets);
+                song_offsets.set(performance.song().id(), offsets);
+            }
+            (ui::Action::EditInputs, stage) => *stage = Self::ChoosingInputs,
+            (ui::Action::Stop, stage) => *stage = Self::ChoosingSong,
+            // Offsets only apply while singing, inputs, options and warnings are handled by the
+            // caller
+            (
+                ui::Action::SetOffsets(_)
+                | ui::Action::SetInput(..)
+                | ui::Action::SetDifficulty(..)
+                | ui::Action::SetOctaveIndependent(..)
+                | ui::Action::DismissWarnings,
+                _,
+            ) => (),
+        }
+    }
+
+    /// Score what each player slot sang
+    fn record(&mut self, detections: &[Option<Detection>]) {
+        if let Self::Singing(performance) = self {
+            let detected = detections
+                .iter()
+                .enumerate()
+                .filter_map(|(slot, detection)| Some((slot, detection.as_ref()?)));
+            for (slot, detection) in detected {
+                performance.push(slot, &detection.pitch_sample());
+            }
+        }
+    }
+
+    /// Stop singing once the song is over, returning the finished performance
+    fn update(&mut self, now: f64) -> Option<Performance> {
+        if !matches!(self, Self::Singing(performance) if performance.is_over(now)) {
+            return None;
+        }
+        match std::mem::replace(self, Self::ChoosingSong) {
+            Self::Singing(performance) => Some(performance),
+            Self::ChoosingSong | Self::Results(_) | Self::ChoosingInputs => None,
+        }
+    }
+
+    /// Show the results of a finished performance of `song`
+    fn results(song: &Song, results: Vec<PlayerResult>) -> Self {
+        Self::Results(
+            results
+                .into_iter()
+                .map(|result| {
+                    let metrics = result.recording.metrics(song).unwrap_or_else(|err| {
+                        log::error!("Failed to analyze performance: {}", err);
+                        Metrics::default()
+                    });
+                    (result.score, metrics)
+                })
+                .collect(),
+        )
+    }
+
+    fn screen<'a>(
+        &'a self,
+        library: &'a model::Library,
+        inputs: impl FnOnce() -> ui::Inputs,
+        now: f64,
+    ) -> ui::Screen<'a> {
+        match self {
+            Self::ChoosingSong => ui::Screen::Songs(library),
+            Self::Singing(performance) => ui::Screen::Singing(performance, now),
+            Self::Results(results) => ui::Screen::Results(results),
+            Self::ChoosingInputs => ui::Screen::Inputs(inputs()),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch
+fn unix_time() -> u64 {
+    instant::SystemTime::now()
+        .duration_since(instant::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Id the highscores of player `slot` are kept under. The first slot is sung by the user, the
+/// other slots by guests.
+fn player_id(user: &User, slot: usize) -> String {
+    if slot == 0 {
+        user.id.clone()
+    } else {
+        format!("guest-{}", slot + 1)
+    }
+}
+
+/// Keep the results of a finished performance of `song`. The highscores of all player slots are
+/// kept, the statistics of the user only hear about the first slot.
+fn keep_results(
+    userdata: &mut UserData,
+    highscores: &mut Highscores,
+    song: &Song,
+    results: &[PlayerResult],
+) {
+    let date = unix_time();
+    if let Some(result) = results.first() {
+        userdata
+            .stats
+            .add(song, &result.recording, &result.score, date);
+    }
+    for (slot, result) in results.iter().enumerate() {
+        highscores.add(Highscore {
+            key: HighscoreKey {
+                song: song.id(),
+                difficulty: result.score.difficulty,
+                voice: result.voice,
+            },
+            user: player_id(&userdata.user, slot),
+            date,
+            score: result.score.clone(),
+            offsets: result.recording.offsets,
+        });
+    }
+}
+
 /// Cross-platform `main` function
 ///
 /// # Panics
@@ -80,19 +380,84 @@ impl SettingsTrait for UserData {}
 pub fn run(platform: Platform) {
     // wrap code in IIFE to write any errors to log before panicing
     (|| -> anyhow::Result<()> {
-        let userdata = Platform::load_userdata("default")?;
+        let mut userdata = Platform::load_userdata("default")?;
         let renderer = platform.create_renderer(&userdata.gfx)?;
         let audio = Audio::init(&userdata.audio)?;
-        let note_input = audio.default_note_input()?;
         info!("Audio Inputs: {:?}", audio.list_note_inputs());
+        let fallback = userdata
+            .fallback_input
+            .clone()
+            .or_else(|| audio.default_note_input_id());
+        let (mut note_inputs, mut warnings) =
+            supervise_note_inputs(&audio, &userdata.inputs, fallback);
         let library = model::Library::init(&userdata.library);
+        // Highscores which fail to load are kept as they are, so they may still be repaired
+        let (mut highscores, highscores_loaded) = match Platform::load_highscores() {
+            Ok(highscores) => (highscores, true),
+            Err(err) => {
+                log::error!(
+                    "Failed to load highscores, new ones won't be saved: {}",
+                    err
+                );
+                (Highscores::default(), false)
+            }
+        };
         let mut main_ui = ui::MainUI::new(&userdata.ui, renderer.get_window());
         info!("Library with {} songs", library.len());
+        info!("{} highscores", highscores.len());
+        let mut stage = Stage::ChoosingSong;
         platform.run(move |event, _| match event {
-            Event::RedrawRequested(_) => main_ui.render(&renderer),
+            Event::RedrawRequested(_) => {
+                let detections = read_note_inputs(&audio, &mut note_inputs);
+                let now = audio.now();
+                stage.record(&detections);
+                if let Some(performance) = stage.update(now) {
+                    let (song, results) = performance.finish();
+                    keep_results(&mut userdata, &mut highscores, &song, &results);
+                    stage = Stage::results(&song, results);
+                }
+                // Listed from the devices enumerated in the background, so this doesn't block
+                let available = audio.list_note_inputs();
+                let inputs = || {
+                    let options = &userdata.player_options;
+                    input_choices(&available, &note_inputs, &userdata.inputs, options)
+                };
+                let action =
+                    main_ui.render(&renderer, &stage.screen(&library, inputs, now), &warnings);
+                match action {
+                    Some(ui::Action::SetInput(slot, choice)) => {
+                        let inputs = &mut userdata.inputs;
+                        choose_input(&available, &mut note_inputs, inputs, slot, choice);
+                    }
+                    Some(ui::Action::SetDifficulty(slot, difficulty)) => {
+                        player_options(&mut userdata.player_options, slot).difficulty = difficulty;
+                    }
+                    Some(ui::Action::SetOctaveIndependent(slot, octave_independent)) => {
+                        let options = player_options(&mut userdata.player_options, slot);
+                        options.octave_independent = octave_independent;
+                    }
+                    Some(ui::Action::DismissWarnings) => warnings.clear(),
+                    Some(action) => {
+                        let players = note_inputs.len();
+                        let offsets = &mut userdata.song_offsets;
+                        let options = &userdata.player_options;
+                        stage.apply(action, &library, offsets, options, now, players);
+                    }
+                    None => (),
+                }
+            }
+            // Lyrics and inputs change without window events
+            Event::MainEventsCleared
+                if matches!(stage, Stage::Singing(_) | Stage::ChoosingInputs) =>
+            {
+                renderer.get_window().request_redraw();
+            }
             Event::UserEvent(Signals::Exit) => {
                 Platform::persist_userdata(&userdata).expect("Persisting settings failed");
-                note_input.read_current().unwrap();
+                if highscores_loaded {
+                    Platform::persist_highscores(&highscores)
+                        .expect("Persisting highscores failed");
+                }
             }
             Event::WindowEvent { event, .. } => main_ui.push_event(event),
             _ => (),
@@ -7,6 +7,26 @@ fn main() {
     #[cfg(not(target_arch = "wasm32"))]
     {
         env_logger::init();
+        // `ultrustar lint <dir>...` validates the songs in the given directories
+        // `ultrustar convert <file> [--track N] [--pitch-offset K]` prints a MIDI file as `.txt`
+        let mut args = std::env::args().skip(1);
+        match args.next().as_deref() {
+            Some("lint") => {
+                let ok = core::lint(args.map(std::path::PathBuf::from).collect());
+                std::process::exit(i32::from(!ok));
+            }
+            Some("convert") => match core::convert(args.collect()) {
+                Ok(txt) => {
+                    print!("{}", txt);
+                    std::process::exit(0);
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            },
+            _ => (),
+        }
         use platform::{Platform, PlatformApi};
         type Settings = <Platform as PlatformApi>::Settings;
         let settings = Settings {};
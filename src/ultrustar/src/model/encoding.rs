@@ -0,0 +1,204 @@
+//! Text encoding detection for song files
+//!
+//! Many older song files are not UTF-8 but CP1252 or Latin-1.
+//! Before handing them to `ultrastar_txt` we therefore decode the raw bytes using the following chain:
+//! 1. A byte order mark, if present
+//! 2. The `#ENCODING` header tag, if present and supported
+//! 3. A heuristic: Valid UTF-8 stays UTF-8, everything else is assumed to be CP1252
+
+use serde::{Deserialize, Serialize};
+
+/// Text encodings we know how to decode
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1252,
+    Latin1,
+}
+impl Encoding {
+    /// Look up an encoding by the name used in `#ENCODING` header tags
+    #[must_use]
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_uppercase().as_str() {
+            "UTF8" | "UTF-8" => Some(Self::Utf8),
+            "CP1252" | "WINDOWS-1252" => Some(Self::Windows1252),
+            "LATIN1" | "ISO-8859-1" | "ISO8859-1" => Some(Self::Latin1),
+            _ => None,
+        }
+    }
+}
+
+/// How an `Encoding` was determined
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Method {
+    ByteOrderMark,
+    Header,
+    Heuristic,
+}
+
+/// Result of encoding detection
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Detection {
+    pub encoding: Encoding,
+    pub method: Method,
+}
+
+/// Characters for CP1252 bytes `0x80..=0x9F`.
+///
+/// Positions that are undefined in CP1252 decode to the respective C1 control character.
+const CP1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+fn detect_bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => Some((Encoding::Utf8, 3)),
+        [0xFF, 0xFE, ..] => Some((Encoding::Utf16Le, 2)),
+        [0xFE, 0xFF, ..] => Some((Encoding::Utf16Be, 2)),
+        _ => None,
+    }
+}
+
+/// Find the value of the `#ENCODING` tag.
+///
+/// Header lines are plain ASCII in all supported encodings, so we can search them before decoding.
+fn find_encoding_tag(bytes: &[u8]) -> Option<&str> {
+    bytes
+        .split(|b| *b == b'\n')
+        .map(<[u8]>::trim_ascii)
+        .filter(|line| !line.is_empty())
+        .take_while(|line| line.starts_with(b"#"))
+        .find_map(|line| {
+            let colon = line.iter().position(|b| *b == b':')?;
+            let (key, value) = (&line[1..colon], &line[colon + 1..]);
+            if key.eq_ignore_ascii_case(b"ENCODING") {
+                std::str::from_utf8(value).ok()
+            } else {
+                None
+            }
+        })
+}
+
+fn decode_utf16(bytes: &[u8], to_u16: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| to_u16([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn decode_single_byte(bytes: &[u8], encoding: Encoding) -> String {
+    bytes
+        .iter()
+        .map(|&b| match (encoding, b) {
+            (Encoding::Windows1252, 0x80..=0x9F) => CP1252_HIGH[usize::from(b - 0x80)],
+            _ => char::from(b),
+        })
+        .collect()
+}
+
+/// Decode `bytes` with a known `encoding`.
+///
+/// Invalid sequences in multi-byte encodings are replaced by U+FFFD.
+#[must_use]
+pub fn decode_as(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        Encoding::Windows1252 | Encoding::Latin1 => decode_single_byte(bytes, encoding),
+    }
+}
+
+/// Detect the encoding of a song file and decode it to UTF-8.
+///
+/// A leading byte order mark is not part of the returned string.
+#[must_use]
+pub fn decode(bytes: &[u8]) -> (String, Detection) {
+    if let Some((encoding, bom_len)) = detect_bom(bytes) {
+        let detection = Detection {
+            encoding,
+            method: Method::ByteOrderMark,
+        };
+        return (decode_as(&bytes[bom_len..], encoding), detection);
+    }
+    let utf8 = std::str::from_utf8(bytes).ok();
+    // A file declaring UTF-8 which isn't valid UTF-8 has most likely been re-saved by
+    // an editor that didn't care about the tag, so we leave it to the heuristic.
+    let declared = find_encoding_tag(bytes)
+        .and_then(Encoding::from_label)
+        .filter(|encoding| *encoding != Encoding::Utf8 || utf8.is_some());
+    if let Some(encoding) = declared {
+        let detection = Detection {
+            encoding,
+            method: Method::Header,
+        };
+        return (decode_as(bytes, encoding), detection);
+    }
+    let (text, encoding) = match utf8 {
+        Some(text) => (text.to_owned(), Encoding::Utf8),
+        None => (
+            decode_as(bytes, Encoding::Windows1252),
+            Encoding::Windows1252,
+        ),
+    };
+    let detection = Detection {
+        encoding,
+        method: Method::Heuristic,
+    };
+    (text, detection)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, Detection, Encoding, Method};
+
+    #[test]
+    fn bom() {
+        let (text, detection) = decode(b"\xEF\xBB\xBF#TITLE:\xC3\xBCber");
+        assert_eq!(text, "#TITLE:über");
+        assert_eq!(detection.method, Method::ByteOrderMark);
+        let (text, detection) = decode(b"\xFF\xFE#\0\xFC\0");
+        assert_eq!(text, "#ü");
+        assert_eq!(detection.encoding, Encoding::Utf16Le);
+    }
+
+    #[test]
+    fn header() {
+        let (text, detection) = decode(b"#ENCODING:CP1252\n#TITLE:\x80\xFC");
+        assert_eq!(text, "#ENCODING:CP1252\n#TITLE:€ü");
+        assert_eq!(
+            detection,
+            Detection {
+                encoding: Encoding::Windows1252,
+                method: Method::Header
+            }
+        );
+        let (text, _) = decode(b"#encoding: iso-8859-1\r\n#TITLE:\x80");
+        assert_eq!(text, "#encoding: iso-8859-1\r\n#TITLE:\u{80}");
+        // Tags after the header must not be considered
+        let (_, detection) = decode(b"#TITLE:a\n: 0 1 0 x\n#ENCODING:CP1252");
+        assert_eq!(detection.method, Method::Heuristic);
+    }
+
+    #[test]
+    fn heuristic() {
+        let (text, detection) = decode("#TITLE:Grüße".as_bytes());
+        assert_eq!(text, "#TITLE:Grüße");
+        assert_eq!(detection.encoding, Encoding::Utf8);
+        let (text, detection) = decode(b"#ENCODING:UTF8\n#TITLE:Gr\xFC\xDFe");
+        assert_eq!(text, "#ENCODING:UTF8\n#TITLE:Grüße");
+        assert_eq!(
+            detection,
+            Detection {
+                encoding: Encoding::Windows1252,
+                method: Method::Heuristic
+            }
+        );
+    }
+}
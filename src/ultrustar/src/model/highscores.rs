@@ -0,0 +1,202 @@
+//! Best scores of each song
+//!
+//! Scores are only comparable when they were sung on the same song, voice and difficulty, which
+//! make up the `HighscoreKey`. Entries of all users are kept in one `Highscores` store, which the
+//! platform persists.
+
+use super::{
+    scoring::{Difficulty, Score},
+    timing::Offsets,
+    SongId,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// What scores are ranked by
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HighscoreKey {
+    pub song: SongId,
+    pub difficulty: Difficulty,
+    pub voice: usize,
+}
+
+/// A single result
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Highscore {
+    pub key: HighscoreKey,
+    /// Id of the user who sang
+    pub user: String,
+    /// Seconds since the Unix epoch
+    pub date: u64,
+    pub score: Score,
+    /// Offsets the song was sung with
+    #[serde(default)]
+    pub offsets: Offsets,
+}
+impl Highscore {
+    /// Whether `other` describes the same result, e.g. when importing the same file twice
+    fn same_result(&self, other: &Self) -> bool {
+        self.key == other.key
+            && self.user == other.user
+            && self.date == other.date
+            && self.score.total() == other.score.total()
+    }
+}
+
+/// Results of all users, each list of a key ordered from best to worst
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Highscores(Vec<Highscore>);
+impl Highscores {
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add a result, keeping the order. Ties rank the earlier result higher.
+    pub fn add(&mut self, entry: Highscore) {
+        let total = entry.score.total();
+        let idx = self
+            .0
+            .iter()
+            .position(|other| {
+                let other_total = other.score.total();
+                other_total < total || (other_total == total && other.date > entry.date)
+            })
+            .unwrap_or(self.0.len());
+        self.0.insert(idx, entry);
+    }
+
+    /// The best `n` results of all users for `key`
+    pub fn top<'a>(
+        &'a self,
+        key: &'a HighscoreKey,
+        n: usize,
+    ) -> impl Iterator<Item = &'a Highscore> + 'a {
+        self.0.iter().filter(move |entry| entry.key == *key).take(n)
+    }
+
+    /// The best `n` results of `user` for `key`
+    pub fn top_of_user<'a>(
+        &'a self,
+        key: &'a HighscoreKey,
+        user: &'a str,
+        n: usize,
+    ) -> impl Iterator<Item = &'a Highscore> + 'a {
+        self.0
+            .iter()
+            .filter(move |entry| entry.key == *key && entry.user == user)
+            .take(n)
+    }
+
+    /// Only keep the best `n` results of each user and key
+    pub fn prune(&mut self, n: usize) {
+        let mut kept: Vec<Highscore> = Vec::with_capacity(self.0.len());
+        for entry in std::mem::take(&mut self.0) {
+            let better = kept
+                .iter()
+                .filter(|other| other.key == entry.key && other.user == entry.user)
+                .count();
+            if better < n {
+                kept.push(entry);
+            }
+        }
+        self.0 = kept;
+    }
+
+    /// Serialize all results, e.g. to move them to another device
+    ///
+    /// # Errors
+    ///
+    /// If serialization fails
+    pub fn export(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Add the results of an `export`, skipping those already present.
+    ///
+    /// Returns the number of added results.
+    ///
+    /// # Errors
+    ///
+    /// If `json` isn't an export of highscores
+    pub fn import(&mut self, json: &str) -> Result<usize> {
+        let imported: Self = serde_json::from_str(json)?;
+        let mut added = 0;
+        for entry in imported.0 {
+            if !self.0.iter().any(|other| other.same_result(&entry)) {
+                self.add(entry);
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Highscore, HighscoreKey, Highscores};
+    use crate::model::{
+        scoring::{test::SONG_TXT, Difficulty, Score},
+        timing::Offsets,
+        Song,
+    };
+
+    fn entry(key: &HighscoreKey, user: &str, date: u64, normal: f64) -> Highscore {
+        Highscore {
+            key: key.clone(),
+            user: user.into(),
+            date,
+            score: Score {
+                normal,
+                difficulty: key.difficulty,
+                ..Score::default()
+            },
+            offsets: Offsets::default(),
+        }
+    }
+
+    #[test]
+    fn ranking() {
+        let key = HighscoreKey {
+            song: Song::from_txt_str(SONG_TXT).unwrap().id(),
+            difficulty: Difficulty::Medium,
+            voice: 0,
+        };
+        let hard = HighscoreKey {
+            difficulty: Difficulty::Hard,
+            ..key.clone()
+        };
+        let mut highscores = Highscores::default();
+        highscores.add(entry(&key, "alice", 1, 5000.));
+        highscores.add(entry(&key, "bob", 2, 7000.));
+        highscores.add(entry(&key, "alice", 3, 6000.));
+        highscores.add(entry(&key, "alice", 4, 5000.));
+        highscores.add(entry(&hard, "alice", 5, 9000.));
+
+        let dates = |entries: Vec<&Highscore>| entries.iter().map(|e| e.date).collect::<Vec<_>>();
+        assert_eq!(dates(highscores.top(&key, 3).collect()), vec![2, 3, 1]);
+        assert_eq!(
+            dates(highscores.top_of_user(&key, "alice", 10).collect()),
+            vec![3, 1, 4]
+        );
+        assert_eq!(dates(highscores.top(&hard, 10).collect()), vec![5]);
+
+        let exported = highscores.export().unwrap();
+        highscores.prune(1);
+        assert_eq!(highscores.len(), 3);
+        assert_eq!(dates(highscores.top(&key, 10).collect()), vec![2, 3]);
+
+        assert_eq!(highscores.import(&exported).unwrap(), 2);
+        assert_eq!(highscores.import(&exported).unwrap(), 0);
+        assert_eq!(
+            dates(highscores.top_of_user(&key, "alice", 10).collect()),
+            vec![3, 1, 4]
+        );
+        assert!(highscores.import("{}").is_err());
+    }
+}
@@ -4,15 +4,41 @@
 //! * Support for multiple loader types with dynamic availability depending on platform
 //! * Persistable song library (cache for loader results)
 
-use super::Song;
+use super::{encoding, lint, midi, Song, SongId};
 use anyhow::anyhow;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::ops::Deref;
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+pub mod fs;
+pub mod ini;
+pub mod kar;
+pub mod performous;
+pub mod rockband;
+pub mod txt;
 
 /// Settings used for song library initialization
 #[derive(Default, Serialize, Deserialize)]
-pub struct Settings;
+pub struct Settings {
+    /// Directories to search for songs
+    #[serde(default)]
+    song_dirs: Vec<PathBuf>,
+    /// Options for importing `.kar` and MIDI files
+    #[serde(default)]
+    midi_import: midi::ImportOptions,
+}
+impl Settings {
+    #[must_use]
+    pub fn with_song_dirs(song_dirs: Vec<PathBuf>) -> Self {
+        Self {
+            song_dirs,
+            ..Self::default()
+        }
+    }
+}
 impl crate::SettingsTrait for Settings {}
 
 /// Song metadata provided by `Loader`s' `crawl` functionality.
@@ -24,6 +50,25 @@ pub struct LoaderSong {
     //
     // This could be a path to a file or a integer index...
     loader_key: String,
+    /// Observations made while crawling, e.g. for display in a song browser
+    #[serde(default)]
+    diagnostics: Vec<CrawlDiagnostic>,
+}
+
+/// Non-fatal information about a song that a `Loader` gathered during `crawl`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum CrawlDiagnostic {
+    /// The text encoding the song file was decoded from
+    Encoding(encoding::Detection),
+    /// A problem found by validating the song
+    Lint(lint::Diagnostic),
+}
+impl CrawlDiagnostic {
+    /// Validate a song whose media files are located in `dir`
+    #[must_use]
+    pub fn lint(song: &Song, dir: &Path) -> Vec<Self> {
+        song.lint(Some(dir)).into_iter().map(Self::Lint).collect()
+    }
 }
 
 /// Global identifier for a loader
@@ -53,6 +98,37 @@ impl Loaders {
         let loaders: Vec<Box<dyn Loader>> = vec![];
         Self { loaders }
     }
+
+    /// Loaders for songs in the song directories.
+    ///
+    /// Not available on the web.
+    fn song_dirs(settings: &Settings) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let loaders: Vec<Box<dyn Loader>> = vec![
+            Box::new(txt::TxtLoader::new(settings.song_dirs.clone())),
+            Box::new(performous::PerformousLoader::new(
+                settings.song_dirs.clone(),
+            )),
+            Box::new(kar::KarLoader::new(
+                settings.song_dirs.clone(),
+                settings.midi_import.clone(),
+            )),
+            Box::new(rockband::RockBandLoader::new(settings.song_dirs.clone())),
+        ];
+        #[cfg(target_arch = "wasm32")]
+        let loaders = {
+            let _ = settings;
+            vec![]
+        };
+        Self { loaders }
+    }
+
+    /// The builtin loaders and those for the song directories
+    fn from_settings(settings: &Settings) -> Self {
+        let mut loaders = Self::builtin();
+        loaders.loaders.extend(Self::song_dirs(settings).loaders);
+        loaders
+    }
 }
 impl Deref for Loaders {
     type Target = [Box<dyn Loader>];
@@ -68,6 +144,40 @@ pub struct LibrarySong {
     loader: LoaderId,
 }
 
+impl LibrarySong {
+    #[must_use]
+    pub fn id(&self) -> SongId {
+        SongId::from_header(&self.metadata.infos)
+    }
+
+    /// Artist and title for display
+    #[must_use]
+    pub fn name(&self) -> String {
+        format!(
+            "{} - {}",
+            self.metadata.infos.artist.trim(),
+            self.metadata.infos.title.trim()
+        )
+    }
+
+    /// Where the loader found the song, usually a path
+    #[must_use]
+    pub fn loader_key(&self) -> &str {
+        &self.metadata.loader_key
+    }
+
+    /// Problems found while crawling
+    pub fn lint_diagnostics(&self) -> impl Iterator<Item = &lint::Diagnostic> {
+        self.metadata
+            .diagnostics
+            .iter()
+            .filter_map(|diagnostic| match diagnostic {
+                CrawlDiagnostic::Lint(diagnostic) => Some(diagnostic),
+                CrawlDiagnostic::Encoding(_) => None,
+            })
+    }
+}
+
 /// Library of playable songs
 pub struct Library {
     loaders: Loaders,
@@ -92,8 +202,14 @@ impl Library {
     }
 
     #[must_use]
-    pub fn init(_settings: &Settings) -> Self {
-        Self::from_loaders(Loaders::builtin())
+    pub fn init(settings: &Settings) -> Self {
+        Self::from_loaders(Loaders::from_settings(settings))
+    }
+
+    /// Library of just the songs in the song directories, e.g. to check a collection
+    #[must_use]
+    pub fn init_song_dirs(settings: &Settings) -> Self {
+        Self::from_loaders(Loaders::song_dirs(settings))
     }
 
     #[must_use]
@@ -128,15 +244,15 @@ impl Deref for Library {
 
 #[cfg(debug_assertions)]
 mod devel {
-    use super::{Loader, LoaderSong, Result, Song};
+    use super::{encoding, CrawlDiagnostic, Loader, LoaderSong, Result, Song};
     use anyhow::anyhow;
 
-    const TXTS: [&str; 2] = [
-        include_str!(concat!(
+    const TXTS: [&[u8]; 2] = [
+        include_bytes!(concat!(
             env!("CARGO_MANIFEST_DIR"),
             "/res/ultrastar-songs-libre-3/Joshua Morin - On the run/Joshua Morin - On the run.txt"
         )),
-        include_str!(concat!(
+        include_bytes!(concat!(
             env!("CARGO_MANIFEST_DIR"),
             "/res/ultrastar-songs-libre-3/Thor - Free Software Song/Thor - Free Software Song.txt"
         )),
@@ -152,27 +268,29 @@ mod devel {
         fn crawl(&self) -> Vec<super::LoaderSong> {
             TXTS.iter()
                 .copied()
-                .map(ultrastar_txt::parse_txt_header_str)
-                .map(Result::unwrap)
+                .map(encoding::decode)
                 .enumerate()
-                .map(|(idx, infos)| LoaderSong {
-                    infos,
-                    loader_key: idx.to_string(),
+                .map(|(idx, (txtstr, detection))| {
+                    let song = Song::from_txt_str(&txtstr).unwrap();
+                    // The media files are not necessarily next to the binary
+                    let mut diagnostics = vec![CrawlDiagnostic::Encoding(detection)];
+                    diagnostics.extend(song.lint(None).into_iter().map(CrawlDiagnostic::Lint));
+                    LoaderSong {
+                        infos: song.txt.header,
+                        loader_key: idx.to_string(),
+                        diagnostics,
+                    }
                 })
                 .collect()
         }
 
         fn load(&self, song: &LoaderSong) -> Result<super::Song> {
             let idx: usize = song.loader_key.parse()?;
-            let txtstr = TXTS
+            let txtbytes = TXTS
                 .get(idx)
                 .ok_or_else(|| anyhow!("Invalid index {}", idx))?;
-            let header = ultrastar_txt::parse_txt_header_str(txtstr)
-                .map_err(|err| anyhow!(err.to_string()))?;
-            let lines = ultrastar_txt::parse_txt_lines_str(txtstr)
-                .map_err(|err| anyhow!(err.to_string()))?;
-            let txt = ultrastar_txt::TXTSong { header, lines };
-            Ok(Song { txt })
+            let (txtstr, _) = encoding::decode(txtbytes);
+            Song::from_txt_str(&txtstr)
         }
     }
 }
@@ -180,7 +298,8 @@ mod devel {
 #[cfg(test)]
 mod test {
     use crate::model::{
-        library::{devel::ExamplesLoader, LibrarySong, Loader, LoaderSong},
+        encoding,
+        library::{devel::ExamplesLoader, CrawlDiagnostic, LibrarySong, Loader, LoaderSong},
         Library,
     };
 
@@ -194,6 +313,16 @@ mod test {
         assert_eq!(2, library.len());
         let existing = &library[0];
         assert!(library.load(existing).is_ok());
+        assert!(matches!(
+            existing.metadata.diagnostics[..],
+            [
+                CrawlDiagnostic::Encoding(encoding::Detection {
+                    encoding: encoding::Encoding::Utf8,
+                    ..
+                }),
+                ..
+            ]
+        ));
         let missing_loader = LibrarySong {
             metadata: existing.metadata.clone(),
             loader: "foo",
@@ -202,6 +331,7 @@ mod test {
         let missing_metadata = LoaderSong {
             infos: existing.metadata.infos.clone(),
             loader_key: "bar".into(),
+            diagnostics: vec![],
         };
         let missing_song = LibrarySong {
             metadata: missing_metadata,
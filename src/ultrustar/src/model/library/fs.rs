@@ -0,0 +1,75 @@
+//! Filesystem crawling shared by `Loader`s which read songs from directories
+
+use log::warn;
+use std::path::{Path, PathBuf};
+
+/// Recursively collect all directories below (and including) `roots`.
+///
+/// Unreadable directories are skipped with a warning.
+#[must_use]
+pub fn walk_dirs(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut pending = roots.to_vec();
+    let mut dirs = Vec::new();
+    while let Some(dir) = pending.pop() {
+        match std::fs::read_dir(&dir) {
+            Ok(entries) => pending.extend(
+                entries
+                    .filter_map(std::result::Result::ok)
+                    .filter(|entry| entry.file_type().is_ok_and(|ty| ty.is_dir()))
+                    .map(|entry| entry.path()),
+            ),
+            Err(err) => {
+                warn!("Skipping {}: {}", dir.display(), err);
+                continue;
+            }
+        }
+        dirs.push(dir);
+    }
+    dirs.sort();
+    dirs
+}
+
+/// Find a file in `dir` by name, ignoring ASCII case.
+#[must_use]
+pub fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(std::result::Result::ok)
+        .find(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .eq_ignore_ascii_case(name)
+        })
+        .map(|entry| entry.path())
+}
+
+/// Find the first of `names` which exists in `dir` and return its file name.
+#[must_use]
+pub fn find_any_file(dir: &Path, names: &[&str]) -> Option<PathBuf> {
+    names
+        .iter()
+        .find_map(|name| find_file(dir, name))
+        .and_then(|path| path.file_name().map(PathBuf::from))
+}
+
+/// List the files directly inside `dir` with one of the given extensions, ignoring ASCII case.
+#[must_use]
+pub fn files_with_extensions(dir: &Path, extensions: &[&str]) -> Vec<PathBuf> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(std::result::Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path.extension().is_some_and(|ext| {
+                    extensions
+                        .iter()
+                        .any(|wanted| ext.to_string_lossy().eq_ignore_ascii_case(wanted))
+                })
+        })
+        .collect();
+    files.sort();
+    files
+}
@@ -0,0 +1,35 @@
+//! Reader for the `song.ini` files used by Frets on Fire descendants and Performous
+
+use std::collections::HashMap;
+
+/// Key-value pairs of a `song.ini`.
+///
+/// Section headers are ignored since all relevant keys live in `[song]` anyways.
+/// Keys are stored in lower case.
+#[derive(Default, Debug)]
+pub struct SongIni(HashMap<String, String>);
+impl SongIni {
+    #[must_use]
+    pub fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with(['[', ';', '#']))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_owned()))
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        Self(entries)
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Look up a numeric value
+    #[must_use]
+    pub fn get_number<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key)?.parse().ok()
+    }
+}
@@ -0,0 +1,72 @@
+//! Loader for `.kar` karaoke files and other MIDI files with lyrics
+
+use super::{fs, CrawlDiagnostic, Loader, LoaderId, LoaderSong};
+use crate::model::{midi, Song};
+use anyhow::Result;
+use log::warn;
+use std::path::{Path, PathBuf};
+
+/// Rock Band style `notes.mid` files are not karaoke files but handled by their own loader
+const EXCLUDED_FILES: [&str; 1] = ["notes.mid"];
+
+/// `Loader` for MIDI files in the song directories
+pub struct KarLoader {
+    roots: Vec<PathBuf>,
+    options: midi::ImportOptions,
+}
+impl KarLoader {
+    #[must_use]
+    pub fn new(roots: Vec<PathBuf>, options: midi::ImportOptions) -> Self {
+        Self { roots, options }
+    }
+
+    fn import(&self, path: &Path) -> Result<Song> {
+        let mut song = midi::import(&std::fs::read(path)?, &self.options)?;
+        let header = &mut song.txt.header;
+        // The MIDI file doubles as accompaniment
+        header.audio_path = path.file_name().map(PathBuf::from).unwrap_or_default();
+        if header.title.is_empty() {
+            header.title = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+        }
+        Ok(song)
+    }
+}
+impl Loader for KarLoader {
+    fn loader_id(&self) -> LoaderId {
+        "kar"
+    }
+
+    fn crawl(&self) -> Vec<LoaderSong> {
+        fs::walk_dirs(&self.roots)
+            .iter()
+            .flat_map(|dir| fs::files_with_extensions(dir, &["kar", "mid", "midi"]))
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                !EXCLUDED_FILES
+                    .iter()
+                    .any(|excluded| name.eq_ignore_ascii_case(excluded))
+            })
+            .filter_map(|path| match self.import(&path) {
+                Ok(song) => Some(LoaderSong {
+                    diagnostics: CrawlDiagnostic::lint(
+                        &song,
+                        path.parent().unwrap_or_else(|| Path::new(".")),
+                    ),
+                    infos: song.txt.header,
+                    loader_key: path.to_string_lossy().into_owned(),
+                }),
+                Err(err) => {
+                    warn!("Skipping {}: {}", path.display(), err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn load(&self, song: &LoaderSong) -> Result<Song> {
+        self.import(Path::new(&song.loader_key))
+    }
+}
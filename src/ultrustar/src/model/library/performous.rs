@@ -0,0 +1,321 @@
+//! Loader for Performous songs in SingStar `notes.xml` format
+//!
+//! Every song lives in a directory of its own, containing a `notes.xml` with the melody,
+//! an optional `song.ini` with metadata and the media files.
+
+use super::{fs, ini::SongIni, CrawlDiagnostic, Loader, LoaderId, LoaderSong};
+use crate::model::{encoding, Song};
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::path::{Path, PathBuf};
+use ultrastar_txt::{Header, Line, Note, TXTSong};
+
+const AUDIO_FILES: [&str; 4] = ["song.ogg", "music.ogg", "song.mp3", "music.mp3"];
+const COVER_FILES: [&str; 3] = ["cover.jpg", "cover.png", "album.png"];
+const BACKGROUND_FILES: [&str; 2] = ["background.jpg", "background.png"];
+const VIDEO_FILES: [&str; 3] = ["video.mp4", "video.avi", "video.mpg"];
+
+/// Read a text file, detecting its encoding
+fn read_text(path: &Path) -> Result<(String, encoding::Detection)> {
+    Ok(encoding::decode(&std::fs::read(path)?))
+}
+
+/// The parts of a `notes.xml` that end up in a `TXTSong`
+#[derive(Debug)]
+struct Melody {
+    bpm: f32,
+    genre: Option<String>,
+    year: Option<u32>,
+    artist: Option<String>,
+    lines: Vec<Line>,
+}
+
+fn is_yes(node: roxmltree::Node, attribute: &str) -> bool {
+    node.attribute(attribute)
+        .is_some_and(|value| value.eq_ignore_ascii_case("yes"))
+}
+
+/// SingStar marks word boundaries with trailing spaces and melismas with `-`,
+/// whereas UltraStar uses leading spaces and `~`.
+fn convert_lyric(lyric: &str, word_start: bool) -> String {
+    let syllable = lyric.trim_end();
+    let syllable = if syllable == "-" { "~" } else { syllable };
+    if word_start && !syllable.starts_with(' ') {
+        format!(" {}", syllable)
+    } else {
+        syllable.to_owned()
+    }
+}
+
+/// Convert the `SENTENCE`s of a single track into lines
+fn convert_sentences<'a, 'input: 'a>(
+    sentences: impl Iterator<Item = roxmltree::Node<'a, 'input>>,
+) -> Result<Vec<Line>> {
+    let mut lines = Vec::new();
+    let mut beat = 0;
+    let mut word_start = false;
+    for sentence in sentences {
+        let mut line = Line {
+            start: beat,
+            rel: None,
+            notes: vec![],
+        };
+        for note in sentence.children().filter(|n| n.has_tag_name("NOTE")) {
+            let duration: i32 = note
+                .attribute("Duration")
+                .ok_or_else(|| anyhow!("NOTE without Duration"))?
+                .parse()?;
+            let midi_note: i32 = note.attribute("MidiNote").unwrap_or("0").parse()?;
+            let start = beat;
+            beat += duration;
+            if midi_note == 0 {
+                // rest
+                continue;
+            }
+            let lyric = note.attribute("Lyric").unwrap_or_default();
+            let text = convert_lyric(lyric, word_start && !line.notes.is_empty());
+            word_start = lyric.ends_with(char::is_whitespace);
+            // UltraStar pitch 0 is the middle C, i.e. MIDI note 60
+            let pitch = midi_note - 60;
+            line.notes.push(if is_yes(note, "FreeStyle") {
+                Note::Freestyle {
+                    start,
+                    duration,
+                    pitch,
+                    text,
+                }
+            } else if is_yes(note, "Bonus") {
+                Note::Golden {
+                    start,
+                    duration,
+                    pitch,
+                    text,
+                }
+            } else {
+                Note::Regular {
+                    start,
+                    duration,
+                    pitch,
+                    text,
+                }
+            });
+        }
+        if let Some(
+            Note::Regular { start, .. }
+            | Note::Golden { start, .. }
+            | Note::Freestyle { start, .. },
+        ) = line.notes.first()
+        {
+            line.start = *start;
+            lines.push(line);
+        }
+        word_start = true;
+    }
+    Ok(lines)
+}
+
+fn parse_notes_xml(xml: &str) -> Result<Melody> {
+    let doc = roxmltree::Document::parse(xml)?;
+    let melody = doc.root_element();
+    if !melody.has_tag_name("MELODY") {
+        return Err(anyhow!("Expected MELODY root element"));
+    }
+    let tempo: f32 = melody
+        .attribute("Tempo")
+        .ok_or_else(|| anyhow!("MELODY without Tempo"))?
+        .parse()?;
+    // Note durations are given in the unit of `Resolution`. UltraStar beats are sixteenth notes.
+    let bpm = match melody.attribute("Resolution").unwrap_or("Semiquaver") {
+        "Semiquaver" => tempo,
+        "Demisemiquaver" => tempo * 2.,
+        other => return Err(anyhow!("Unsupported resolution {}", other)),
+    };
+    let tracks: Vec<_> = melody
+        .children()
+        .filter(|n| n.has_tag_name("TRACK"))
+        .collect();
+    let lines = if tracks.is_empty() {
+        convert_sentences(melody.children().filter(|n| n.has_tag_name("SENTENCE")))?
+    } else {
+        let mut lines = Vec::new();
+        for (player, track) in (1..).zip(&tracks) {
+            let mut track_lines =
+                convert_sentences(track.children().filter(|n| n.has_tag_name("SENTENCE")))?;
+            if tracks.len() > 1 {
+                if let Some(first) = track_lines.first_mut() {
+                    first.notes.insert(0, Note::PlayerChange { player });
+                }
+            }
+            lines.append(&mut track_lines);
+        }
+        lines
+    };
+    Ok(Melody {
+        bpm,
+        genre: melody.attribute("Genre").map(str::to_owned),
+        year: melody.attribute("Year").and_then(|year| year.parse().ok()),
+        artist: tracks
+            .iter()
+            .find_map(|track| track.attribute("Artist"))
+            .map(str::to_owned),
+        lines,
+    })
+}
+
+fn make_header(dir: &Path, ini: &SongIni, melody: &Melody) -> Header {
+    let title = ini
+        .get("name")
+        .map(str::to_owned)
+        .or_else(|| Some(dir.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    Header {
+        title,
+        artist: ini
+            .get("artist")
+            .map(str::to_owned)
+            .or_else(|| melody.artist.clone())
+            .unwrap_or_default(),
+        bpm: melody.bpm,
+        audio_path: fs::find_any_file(dir, &AUDIO_FILES).unwrap_or_default(),
+        gap: ini.get_number("delay"),
+        cover_path: fs::find_any_file(dir, &COVER_FILES),
+        background_path: fs::find_any_file(dir, &BACKGROUND_FILES),
+        video_path: fs::find_any_file(dir, &VIDEO_FILES),
+        video_gap: ini.get_number("video_start_time"),
+        genre: ini
+            .get("genre")
+            .map(str::to_owned)
+            .or_else(|| melody.genre.clone()),
+        edition: ini.get("album").map(str::to_owned),
+        language: ini.get("language").map(str::to_owned),
+        year: ini.get_number("year").or(melody.year),
+        relative: None,
+        unknown: None,
+    }
+}
+
+/// Read a Performous song directory
+fn read_song_dir(dir: &Path) -> Result<(TXTSong, encoding::Detection)> {
+    let notes = fs::find_file(dir, "notes.xml").ok_or_else(|| anyhow!("No notes.xml found"))?;
+    let (xml, detection) = read_text(&notes)?;
+    let melody = parse_notes_xml(&xml)?;
+    let ini = match fs::find_file(dir, "song.ini") {
+        Some(path) => SongIni::parse(&read_text(&path)?.0),
+        None => SongIni::default(),
+    };
+    let header = make_header(dir, &ini, &melody);
+    let lines = melody.lines;
+    Ok((TXTSong { header, lines }, detection))
+}
+
+/// `Loader` for directories of Performous songs
+pub struct PerformousLoader {
+    roots: Vec<PathBuf>,
+}
+impl PerformousLoader {
+    #[must_use]
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots }
+    }
+}
+impl Loader for PerformousLoader {
+    fn loader_id(&self) -> LoaderId {
+        "performous"
+    }
+
+    fn crawl(&self) -> Vec<LoaderSong> {
+        fs::walk_dirs(&self.roots)
+            .into_iter()
+            .filter(|dir| fs::find_file(dir, "notes.xml").is_some())
+            .filter_map(|dir| match read_song_dir(&dir) {
+                Ok((txt, detection)) => {
+                    let song = Song::from(txt);
+                    let mut diagnostics = vec![CrawlDiagnostic::Encoding(detection)];
+                    diagnostics.extend(CrawlDiagnostic::lint(&song, &dir));
+                    Some(LoaderSong {
+                        infos: song.txt.header,
+                        loader_key: dir.to_string_lossy().into_owned(),
+                        diagnostics,
+                    })
+                }
+                Err(err) => {
+                    warn!("Skipping {}: {}", dir.display(), err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn load(&self, song: &LoaderSong) -> Result<Song> {
+        let (txt, _) = read_song_dir(Path::new(&song.loader_key))?;
+        Ok(txt.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_notes_xml;
+    use ultrastar_txt::Note;
+
+    const NOTES_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MELODY xmlns="http://www.singstargame.com" Version="1" Tempo="120" FixedTempo="Yes" Resolution="Demisemiquaver" Genre="Rock" Year="2001">
+  <SENTENCE>
+    <NOTE MidiNote="0" Duration="8" Lyric=""/>
+    <NOTE MidiNote="60" Duration="2" Lyric="Hel"/>
+    <NOTE MidiNote="62" Duration="2" Lyric="lo " Bonus="Yes"/>
+    <NOTE MidiNote="64" Duration="4" Lyric="world"/>
+    <NOTE MidiNote="62" Duration="2" Lyric="-" FreeStyle="Yes"/>
+  </SENTENCE>
+  <SENTENCE>
+    <NOTE MidiNote="0" Duration="4" Lyric=""/>
+    <NOTE MidiNote="55" Duration="4" Lyric="Bye"/>
+  </SENTENCE>
+</MELODY>"#;
+
+    #[test]
+    fn notes_xml() {
+        let melody = parse_notes_xml(NOTES_XML).unwrap();
+        assert!((melody.bpm - 240.).abs() < f32::EPSILON);
+        assert_eq!(melody.year, Some(2001));
+        assert_eq!(melody.lines.len(), 2);
+        assert_eq!(melody.lines[0].start, 8);
+        assert_eq!(
+            melody.lines[0].notes,
+            vec![
+                Note::Regular {
+                    start: 8,
+                    duration: 2,
+                    pitch: 0,
+                    text: "Hel".into()
+                },
+                Note::Golden {
+                    start: 10,
+                    duration: 2,
+                    pitch: 2,
+                    text: "lo".into()
+                },
+                Note::Regular {
+                    start: 12,
+                    duration: 4,
+                    pitch: 4,
+                    text: " world".into()
+                },
+                Note::Freestyle {
+                    start: 16,
+                    duration: 2,
+                    pitch: 2,
+                    text: "~".into()
+                },
+            ]
+        );
+        assert_eq!(
+            melody.lines[1].notes,
+            vec![Note::Regular {
+                start: 22,
+                duration: 4,
+                pitch: -5,
+                text: "Bye".into()
+            }]
+        );
+    }
+}
@@ -0,0 +1,393 @@
+//! Loader for the vocal parts of Rock Band style charts
+//!
+//! Such a chart is a directory with a `song.ini` and a `notes.mid` whose `PART VOCALS` track
+//! contains the melody and lyrics. Phrase markers separate the lines of the song.
+//! Lyrics may carry suffixes: `#` and `^` mark unpitched notes, which we map to freestyle and rap
+//! notes respectively, `-` joins a syllable with the next one, `=` is a literal hyphen and a sole
+//! `+` continues the previous syllable.
+
+use super::{fs, ini::SongIni, CrawlDiagnostic, Loader, LoaderId, LoaderSong};
+use crate::model::{
+    encoding,
+    midi::{self, TempoMap},
+    timing::Timing,
+    NoteKind, NotePos, Song,
+};
+use anyhow::{anyhow, Result};
+use log::warn;
+use midly::{MetaMessage, Smf, TrackEventKind};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use ultrastar_txt::{Header, Line, Note, TXTSong};
+
+const VOCALS_TRACK: &str = "PART VOCALS";
+/// Keys of pitched vocal notes
+const PITCHED_KEYS: std::ops::RangeInclusive<u8> = 36..=84;
+/// Keys marking phrases; 105 is used for the lead vocals and 106 for older two-player charts
+const PHRASE_KEYS: [u8; 2] = [105, 106];
+
+const AUDIO_FILES: [&str; 3] = ["song.ogg", "song.mp3", "guitar.ogg"];
+const COVER_FILES: [&str; 2] = ["album.png", "album.jpg"];
+const BACKGROUND_FILES: [&str; 2] = ["background.png", "background.jpg"];
+const VIDEO_FILES: [&str; 2] = ["video.webm", "video.mp4"];
+
+/// A syllable together with its markers
+struct Syllable {
+    text: String,
+    kind: NoteKind,
+    /// Whether the syllable forms a word with the following one
+    joined: bool,
+}
+impl Syllable {
+    fn parse(lyric: &str) -> Self {
+        let (text, kind) = if let Some(text) = lyric.strip_suffix('#') {
+            (text, NoteKind::Freestyle)
+        } else if let Some(text) = lyric.strip_suffix('^') {
+            (text, NoteKind::Rap)
+        } else {
+            (lyric, NoteKind::Normal)
+        };
+        let (text, joined) = match text.strip_suffix('-') {
+            Some(text) => (text, true),
+            None => (text, false),
+        };
+        Self {
+            text: text.replace('=', "-"),
+            kind,
+            joined,
+        }
+    }
+}
+
+/// Convert the vocals of a `notes.mid` into BPM, song lines and rap note positions
+fn convert_vocals(bytes: &[u8]) -> Result<(f64, Vec<Line>, BTreeSet<NotePos>)> {
+    let smf = Smf::parse(bytes)?;
+    let tempo = TempoMap::from_smf(&smf);
+    let track = smf
+        .tracks
+        .iter()
+        .find(|track| midi::track_name(track).as_deref() == Some(VOCALS_TRACK))
+        .ok_or_else(|| anyhow!("No {} track", VOCALS_TRACK))?;
+    let notes = midi::track_notes(track, PITCHED_KEYS);
+    let phrases = PHRASE_KEYS
+        .iter()
+        .map(|&key| midi::track_notes(track, key..=key))
+        .find(|phrases| !phrases.is_empty())
+        .unwrap_or_default();
+    let mut lyrics: Vec<_> = midi::absolute_ticks(track)
+        .filter_map(|(tick, kind)| match kind {
+            TrackEventKind::Meta(MetaMessage::Lyric(text) | MetaMessage::Text(text))
+                if !text.starts_with(b"[") =>
+            {
+                Some((tick, encoding::decode(text).0.trim().to_owned()))
+            }
+            _ => None,
+        })
+        .collect();
+    lyrics.sort_by_key(|(tick, _)| *tick);
+
+    let mut texts = vec![None; notes.len()];
+    for (tick, lyric) in lyrics {
+        let idx = notes.partition_point(|note| note.end <= tick);
+        if let Some(text) = texts.get_mut(idx) {
+            *text = Some(lyric);
+        }
+    }
+
+    let bpm = tempo.initial_qpm();
+    let timing = Timing::new(bpm, 0.);
+    let beat = |tick| midi::round_beat(timing.secs_to_beat(tempo.secs(tick)));
+    let mut lines: Vec<Line> = Vec::new();
+    let mut raps = BTreeSet::new();
+    let mut current_phrase = None;
+    let mut joined = true;
+    let mut kind = NoteKind::Normal;
+    for (note, lyric) in notes.iter().zip(&texts) {
+        let phrase = phrases.partition_point(|phrase| phrase.end <= note.start);
+        if current_phrase != Some(phrase) || lines.is_empty() {
+            current_phrase = Some(phrase);
+            lines.push(Line {
+                start: beat(note.start),
+                rel: None,
+                notes: vec![],
+            });
+            joined = true;
+            kind = NoteKind::Normal;
+        }
+        let text = match lyric.as_deref() {
+            // Slides continue the previous syllable, including its kind
+            Some("+") => "~".to_owned(),
+            None => {
+                kind = NoteKind::Normal;
+                "~".to_owned()
+            }
+            Some(lyric) => {
+                let syllable = Syllable::parse(lyric);
+                let text = if joined {
+                    syllable.text
+                } else {
+                    format!(" {}", syllable.text)
+                };
+                joined = syllable.joined;
+                kind = syllable.kind;
+                text
+            }
+        };
+        let start = beat(note.start);
+        let duration = (beat(note.end) - start).max(1);
+        let pitch = i32::from(note.key) - midi::MIDDLE_C;
+        let line_idx = lines.len() - 1;
+        let line = &mut lines[line_idx];
+        if kind == NoteKind::Rap {
+            raps.insert((line_idx, line.notes.len()));
+        }
+        line.notes.push(if kind == NoteKind::Freestyle {
+            Note::Freestyle {
+                start,
+                duration,
+                pitch,
+                text,
+            }
+        } else {
+            Note::Regular {
+                start,
+                duration,
+                pitch,
+                text,
+            }
+        });
+    }
+    Ok((bpm, lines, raps))
+}
+
+/// Read a chart directory
+fn read_song_dir(dir: &Path) -> Result<Song> {
+    let notes = fs::find_file(dir, "notes.mid").ok_or_else(|| anyhow!("No notes.mid found"))?;
+    let ini_path = fs::find_file(dir, "song.ini").ok_or_else(|| anyhow!("No song.ini found"))?;
+    let ini = SongIni::parse(&encoding::decode(&std::fs::read(ini_path)?).0);
+    let (bpm, lines, raps) = convert_vocals(&std::fs::read(notes)?)?;
+    #[allow(clippy::cast_possible_truncation)]
+    let header = Header {
+        title: ini
+            .get("name")
+            .map(str::to_owned)
+            .or_else(|| Some(dir.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_default(),
+        artist: ini.get("artist").map(str::to_owned).unwrap_or_default(),
+        bpm: bpm as f32,
+        audio_path: fs::find_any_file(dir, &AUDIO_FILES).unwrap_or_default(),
+        gap: ini.get_number("delay"),
+        cover_path: fs::find_any_file(dir, &COVER_FILES),
+        background_path: fs::find_any_file(dir, &BACKGROUND_FILES),
+        video_path: fs::find_any_file(dir, &VIDEO_FILES),
+        video_gap: ini.get_number("video_start_time"),
+        genre: ini.get("genre").map(str::to_owned),
+        edition: ini.get("album").map(str::to_owned),
+        language: None,
+        year: ini.get_number("year"),
+        relative: None,
+        unknown: None,
+    };
+    let mut song = Song::from(TXTSong { header, lines });
+    song.raps = raps;
+    Ok(song)
+}
+
+/// `Loader` for Rock Band style chart directories
+pub struct RockBandLoader {
+    roots: Vec<PathBuf>,
+}
+impl RockBandLoader {
+    #[must_use]
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots }
+    }
+}
+impl Loader for RockBandLoader {
+    fn loader_id(&self) -> LoaderId {
+        "rockband"
+    }
+
+    fn crawl(&self) -> Vec<LoaderSong> {
+        fs::walk_dirs(&self.roots)
+            .into_iter()
+            .filter(|dir| fs::find_file(dir, "notes.mid").is_some())
+            .filter_map(|dir| match read_song_dir(&dir) {
+                Ok(song) => Some(LoaderSong {
+                    diagnostics: CrawlDiagnostic::lint(&song, &dir),
+                    infos: song.txt.header,
+                    loader_key: dir.to_string_lossy().into_owned(),
+                }),
+                Err(err) => {
+                    warn!("Skipping {}: {}", dir.display(), err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn load(&self, song: &LoaderSong) -> Result<Song> {
+        read_song_dir(Path::new(&song.loader_key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::convert_vocals;
+    use midly::{
+        num::{u15, u24, u28, u4, u7},
+        Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+    };
+    use ultrastar_txt::Note;
+
+    fn event(delta: u32, kind: TrackEventKind) -> TrackEvent {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind,
+        }
+    }
+
+    fn note(delta: u32, key: u8, on: bool) -> TrackEvent<'static> {
+        let vel = u7::new(if on { 100 } else { 0 });
+        event(
+            delta,
+            TrackEventKind::Midi {
+                channel: u4::new(0),
+                message: MidiMessage::NoteOn {
+                    key: u7::new(key),
+                    vel,
+                },
+            },
+        )
+    }
+
+    fn lyric(delta: u32, text: &'static [u8]) -> TrackEvent<'static> {
+        event(delta, TrackEventKind::Meta(MetaMessage::Lyric(text)))
+    }
+
+    #[test]
+    fn vocals() {
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::new(480)),
+        ));
+        smf.tracks.push(vec![event(
+            0,
+            TrackEventKind::Meta(MetaMessage::Tempo(u24::new(500_000))),
+        )]);
+        // One sixteenth note is 120 ticks
+        smf.tracks.push(vec![
+            event(
+                0,
+                TrackEventKind::Meta(MetaMessage::TrackName(b"PART VOCALS")),
+            ),
+            note(0, 105, true),
+            lyric(0, b"Hel-"),
+            note(0, 60, true),
+            note(240, 60, false),
+            lyric(0, b"lo"),
+            note(0, 62, true),
+            note(240, 62, false),
+            note(0, 105, false),
+            note(120, 105, true),
+            lyric(0, b"talk^"),
+            note(0, 50, true),
+            note(240, 50, false),
+            lyric(0, b"+"),
+            note(0, 52, true),
+            note(120, 52, false),
+            note(0, 105, false),
+        ]);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let (bpm, lines, raps) = convert_vocals(&bytes).unwrap();
+        assert!((bpm - 120.).abs() < f64::EPSILON);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0].notes,
+            vec![
+                Note::Regular {
+                    start: 0,
+                    duration: 2,
+                    pitch: 0,
+                    text: "Hel".into()
+                },
+                Note::Regular {
+                    start: 2,
+                    duration: 2,
+                    pitch: 2,
+                    text: "lo".into()
+                },
+            ]
+        );
+        assert_eq!(lines[1].start, 5);
+        assert_eq!(
+            lines[1].notes,
+            vec![
+                Note::Regular {
+                    start: 5,
+                    duration: 2,
+                    pitch: -10,
+                    text: "talk".into()
+                },
+                Note::Regular {
+                    start: 7,
+                    duration: 1,
+                    pitch: -8,
+                    text: "~".into()
+                },
+            ]
+        );
+        assert_eq!(raps.into_iter().collect::<Vec<_>>(), vec![(1, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn markers() {
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::new(480)),
+        ));
+        smf.tracks.push(vec![
+            event(
+                0,
+                TrackEventKind::Meta(MetaMessage::TrackName(b"PART VOCALS")),
+            ),
+            note(0, 105, true),
+            lyric(0, b"Jay=Z^"),
+            note(0, 60, true),
+            note(240, 60, false),
+            note(0, 62, true),
+            note(240, 62, false),
+            note(0, 105, false),
+            note(120, 105, true),
+            note(0, 64, true),
+            note(240, 64, false),
+            lyric(0, b"Caf\xe9"),
+            note(0, 65, true),
+            note(240, 65, false),
+            note(0, 105, false),
+        ]);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let (_, lines, raps) = convert_vocals(&bytes).unwrap();
+        let texts: Vec<Vec<&str>> = lines
+            .iter()
+            .map(|line| {
+                line.notes
+                    .iter()
+                    .map(|note| match note {
+                        Note::Regular { text, .. } => text.as_str(),
+                        _ => panic!("Unexpected {:?}", note),
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(texts, vec![vec!["Jay-Z", "~"], vec!["~", "Café"]]);
+        // Notes without lyrics don't continue the rap syllable
+        assert_eq!(raps.into_iter().collect::<Vec<_>>(), vec![(0, 0)]);
+    }
+}
@@ -0,0 +1,58 @@
+//! Loader for UltraStar `.txt` song files
+
+use super::{fs, CrawlDiagnostic, Loader, LoaderId, LoaderSong};
+use crate::model::{encoding, Song};
+use anyhow::Result;
+use log::warn;
+use std::path::{Path, PathBuf};
+
+/// Read a song file, detecting its encoding
+fn read_song(path: &Path) -> Result<(Song, encoding::Detection)> {
+    let (txtstr, detection) = encoding::decode(&std::fs::read(path)?);
+    Ok((Song::from_txt_str(&txtstr)?, detection))
+}
+
+/// `Loader` for `.txt` files in the song directories
+pub struct TxtLoader {
+    roots: Vec<PathBuf>,
+}
+impl TxtLoader {
+    #[must_use]
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots }
+    }
+}
+impl Loader for TxtLoader {
+    fn loader_id(&self) -> LoaderId {
+        "txt"
+    }
+
+    fn crawl(&self) -> Vec<LoaderSong> {
+        fs::walk_dirs(&self.roots)
+            .iter()
+            .flat_map(|dir| fs::files_with_extensions(dir, &["txt"]))
+            .filter_map(|path| match read_song(&path) {
+                Ok((song, detection)) => {
+                    let mut diagnostics = vec![CrawlDiagnostic::Encoding(detection)];
+                    diagnostics.extend(CrawlDiagnostic::lint(
+                        &song,
+                        path.parent().unwrap_or_else(|| Path::new(".")),
+                    ));
+                    Some(LoaderSong {
+                        infos: song.txt.header,
+                        loader_key: path.to_string_lossy().into_owned(),
+                        diagnostics,
+                    })
+                }
+                Err(err) => {
+                    warn!("Skipping {}: {}", path.display(), err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn load(&self, song: &LoaderSong) -> Result<Song> {
+        read_song(Path::new(&song.loader_key)).map(|(song, _)| song)
+    }
+}
@@ -0,0 +1,364 @@
+//! Validation of songs
+//!
+//! Finds mistakes which make songs unplayable or unpleasant to sing, e.g. while crawling a
+//! library, in an editor or from the command line. Every problem is reported as a `Diagnostic`
+//! pointing to where it was found.
+
+use super::{midi::MIDDLE_C, NoteKind, NotePos, Song, SungNote};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// BPM values outside this range are most likely typos
+const PLAUSIBLE_BPM: std::ops::RangeInclusive<f32> = 20.0..=2000.0;
+/// MIDI keys from C1 to C7, which covers every human voice
+const SINGABLE_KEYS: std::ops::RangeInclusive<i32> = 24..=96;
+/// Largest plausible distance between the lowest and highest note of a voice in semitones
+const MAX_PITCH_SPAN: i32 = 36;
+/// A duet voice with less than this fraction of the beats of the other one is unbalanced
+const MIN_DUET_SHARE: f64 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    /// The song cannot be played properly
+    Error,
+    /// The song is playable but likely contains a mistake
+    Warning,
+}
+
+/// Where a problem was found. Indices are zero-based.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Location {
+    /// A header tag, without the leading `#`
+    Header(String),
+    Line(usize),
+    Note(NotePos),
+    /// A voice of the song as a whole
+    Voice(usize),
+}
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header(tag) => write!(f, "#{}", tag),
+            Self::Line(line) => write!(f, "line {}", line + 1),
+            Self::Note((line, note)) => write!(f, "line {}, note {}", line + 1, note + 1),
+            Self::Voice(voice) => write!(f, "voice {}", voice + 1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Problem {
+    /// The note starts before the end of the previous note of the same voice
+    OverlappingNotes {
+        previous: NotePos,
+    },
+    NonPositiveLength {
+        duration: i32,
+    },
+    /// The line starts before the end of the given note
+    LineBreakInsideNote {
+        note: NotePos,
+    },
+    MissingMedia {
+        path: PathBuf,
+    },
+    AbsurdBpm {
+        bpm: f32,
+    },
+    /// The number of beats sung by each voice differs a lot
+    UnbalancedDuet {
+        beats: [i32; 2],
+    },
+    WhitespaceSyllable,
+    UnsingablePitch {
+        pitch: i32,
+    },
+    /// The voice covers more octaves than anyone can sing
+    WidePitchRange {
+        lowest: i32,
+        highest: i32,
+    },
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OverlappingNotes { previous } => write!(
+                f,
+                "overlaps with note {} of line {}",
+                previous.1 + 1,
+                previous.0 + 1
+            ),
+            Self::NonPositiveLength { duration } => write!(f, "has a length of {}", duration),
+            Self::LineBreakInsideNote { note } => write!(
+                f,
+                "starts inside note {} of line {}",
+                note.1 + 1,
+                note.0 + 1
+            ),
+            Self::MissingMedia { path } => write!(f, "{} doesn't exist", path.display()),
+            Self::AbsurdBpm { bpm } => write!(f, "BPM of {} is implausible", bpm),
+            Self::UnbalancedDuet { beats } => {
+                write!(f, "voices sing {} and {} beats", beats[0], beats[1])
+            }
+            Self::WhitespaceSyllable => write!(f, "syllable consists of whitespace only"),
+            Self::UnsingablePitch { pitch } => write!(f, "pitch {} cannot be sung", pitch),
+            Self::WidePitchRange { lowest, highest } => {
+                write!(f, "ranges from pitch {} to {}", lowest, highest)
+            }
+        }
+    }
+}
+
+/// A problem found in a song
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub location: Location,
+    pub problem: Problem,
+}
+impl Diagnostic {
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        match &self.problem {
+            Problem::OverlappingNotes { .. } | Problem::NonPositiveLength { .. } => Severity::Error,
+            Problem::MissingMedia { .. } if self.location == Location::Header("MP3".into()) => {
+                Severity::Error
+            }
+            Problem::AbsurdBpm { bpm } if !(*bpm > 0. && bpm.is_finite()) => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity() {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.problem)
+    }
+}
+
+impl Song {
+    /// Check the song for problems.
+    ///
+    /// Media files are looked up relative to `dir` if given.
+    #[must_use]
+    pub fn lint(&self, dir: Option<&Path>) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        self.lint_header(dir, &mut diagnostics);
+        self.lint_notes(&mut diagnostics);
+        for voice in 0..self.voice_count() {
+            self.lint_voice(voice, &mut diagnostics);
+        }
+        self.lint_duet(&mut diagnostics);
+        diagnostics
+    }
+
+    fn lint_header(&self, dir: Option<&Path>, diagnostics: &mut Vec<Diagnostic>) {
+        let header = &self.txt.header;
+        if !PLAUSIBLE_BPM.contains(&header.bpm) {
+            diagnostics.push(Diagnostic {
+                location: Location::Header("BPM".into()),
+                problem: Problem::AbsurdBpm { bpm: header.bpm },
+            });
+        }
+        let Some(dir) = dir else {
+            return;
+        };
+        let media = [
+            ("MP3", Some(&header.audio_path)),
+            ("COVER", header.cover_path.as_ref()),
+            ("BACKGROUND", header.background_path.as_ref()),
+            ("VIDEO", header.video_path.as_ref()),
+        ];
+        for (tag, path) in media {
+            if let Some(path) = path {
+                if path.as_os_str().is_empty() || !dir.join(path).is_file() {
+                    diagnostics.push(Diagnostic {
+                        location: Location::Header(tag.into()),
+                        problem: Problem::MissingMedia { path: path.clone() },
+                    });
+                }
+            }
+        }
+    }
+
+    /// Checks of single notes
+    fn lint_notes(&self, diagnostics: &mut Vec<Diagnostic>) {
+        for (line_idx, line) in self.txt.lines.iter().enumerate() {
+            for (note_idx, note) in line.notes.iter().enumerate() {
+                let Some(sung) = SungNote::from_note(note) else {
+                    continue;
+                };
+                let location = Location::Note((line_idx, note_idx));
+                let mut report = |problem| {
+                    diagnostics.push(Diagnostic {
+                        location: location.clone(),
+                        problem,
+                    });
+                };
+                if sung.duration <= 0 {
+                    report(Problem::NonPositiveLength {
+                        duration: sung.duration,
+                    });
+                }
+                if sung.text.trim().is_empty() {
+                    report(Problem::WhitespaceSyllable);
+                }
+                if !SINGABLE_KEYS.contains(&(sung.pitch + MIDDLE_C)) {
+                    report(Problem::UnsingablePitch { pitch: sung.pitch });
+                }
+            }
+        }
+    }
+
+    /// Checks of the order of notes and lines, which only make sense within a voice
+    fn lint_voice(&self, voice: usize, diagnostics: &mut Vec<Diagnostic>) {
+        let mut report = |diagnostic| {
+            // Lines sung by both voices are checked twice
+            if !diagnostics.contains(&diagnostic) {
+                diagnostics.push(diagnostic);
+            }
+        };
+        let relative = self.txt.header.relative == Some(true);
+        let mut previous: Option<(NotePos, i32)> = None;
+        let mut pitches: Option<(i32, i32)> = None;
+        for line in self.voice_lines(voice) {
+            match previous {
+                Some((note, end)) if !relative && line.start < end => report(Diagnostic {
+                    location: Location::Line(line.index),
+                    problem: Problem::LineBreakInsideNote { note },
+                }),
+                _ => (),
+            }
+            for &(note_idx, sung) in &line.notes {
+                let pos = (line.index, note_idx);
+                match previous {
+                    Some((previous, end)) if !relative && sung.start < end => report(Diagnostic {
+                        location: Location::Note(pos),
+                        problem: Problem::OverlappingNotes { previous },
+                    }),
+                    _ => (),
+                }
+                previous = Some((pos, sung.end()));
+                if self.note_kind(pos) != Some(NoteKind::Freestyle) {
+                    pitches = Some(pitches.map_or((sung.pitch, sung.pitch), |(low, high)| {
+                        (low.min(sung.pitch), high.max(sung.pitch))
+                    }));
+                }
+            }
+        }
+        if let Some((lowest, highest)) = pitches {
+            if highest - lowest > MAX_PITCH_SPAN {
+                report(Diagnostic {
+                    location: Location::Voice(voice),
+                    problem: Problem::WidePitchRange { lowest, highest },
+                });
+            }
+        }
+    }
+
+    fn lint_duet(&self, diagnostics: &mut Vec<Diagnostic>) {
+        if self.voice_count() < 2 {
+            return;
+        }
+        let beats = [0, 1].map(|voice| {
+            self.voice_lines(voice)
+                .iter()
+                .flat_map(|line| line.notes.iter().map(|(_, note)| note.duration.max(0)))
+                .sum::<i32>()
+        });
+        let (weaker, stronger) = if beats[0] < beats[1] { (0, 1) } else { (1, 0) };
+        if f64::from(beats[weaker]) < MIN_DUET_SHARE * f64::from(beats[stronger]) {
+            diagnostics.push(Diagnostic {
+                location: Location::Voice(weaker),
+                problem: Problem::UnbalancedDuet { beats },
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Diagnostic, Location, Problem, Severity};
+    use crate::model::Song;
+
+    #[test]
+    fn problems() {
+        let song = Song::from_txt_str(
+            "#TITLE:Broken
+#ARTIST:Someone
+#MP3:missing.ogg
+#BPM:5000
+#GAP:0
+P1
+: 0 4 0 One
+: 2 2 0 two
+: 4 0 0  three
+- 3
+: 6 2 50 four
+P2
+: 0 1 0 \x20
+E
+",
+        )
+        .unwrap();
+        let diagnostics = song.lint(Some(std::path::Path::new("/nonexistent")));
+        let expected = [
+            (
+                Location::Header("BPM".into()),
+                Problem::AbsurdBpm { bpm: 5000. },
+            ),
+            (
+                Location::Header("MP3".into()),
+                Problem::MissingMedia {
+                    path: "missing.ogg".into(),
+                },
+            ),
+            (
+                Location::Note((0, 3)),
+                Problem::NonPositiveLength { duration: 0 },
+            ),
+            (
+                Location::Note((1, 0)),
+                Problem::UnsingablePitch { pitch: 50 },
+            ),
+            (Location::Note((1, 2)), Problem::WhitespaceSyllable),
+            (
+                Location::Note((0, 2)),
+                Problem::OverlappingNotes { previous: (0, 1) },
+            ),
+            (
+                Location::Line(1),
+                Problem::LineBreakInsideNote { note: (0, 3) },
+            ),
+            (
+                Location::Voice(0),
+                Problem::WidePitchRange {
+                    lowest: 0,
+                    highest: 50,
+                },
+            ),
+            (
+                Location::Voice(1),
+                Problem::UnbalancedDuet { beats: [8, 1] },
+            ),
+        ];
+        assert_eq!(
+            diagnostics,
+            expected
+                .into_iter()
+                .map(|(location, problem)| Diagnostic { location, problem })
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(diagnostics[1].severity(), Severity::Error);
+        assert_eq!(diagnostics[0].severity(), Severity::Warning);
+        assert_eq!(
+            diagnostics[5].to_string(),
+            "error: line 1, note 3: overlaps with note 2 of line 1"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Following the lyrics of a voice while the song plays
+
+use super::{NoteKind, Song};
+
+/// A syllable of a lyric line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Syllable<'a> {
+    pub text: &'a str,
+    pub kind: NoteKind,
+    /// First beat of the note
+    pub start: i32,
+    /// Beat right after the note
+    pub end: i32,
+}
+
+/// The line to display at some beat together with the position within it
+#[derive(Clone, Debug, PartialEq)]
+pub struct LyricCursor<'a> {
+    pub line: Vec<Syllable<'a>>,
+    /// Index of the syllable that is sung or was sung last, `None` before the first one
+    pub current: Option<usize>,
+    /// Fraction of the current syllable that has been sung, from 0 to 1
+    pub progress: f64,
+}
+
+impl Song {
+    /// The lyrics of `voice` at `beat`: the first line that hasn't been sung completely yet.
+    ///
+    /// Returns `None` after the last line.
+    #[must_use]
+    pub fn lyric_cursor(&self, voice: usize, beat: f64) -> Option<LyricCursor<'_>> {
+        let line = self.voice_lines(voice).into_iter().find(|line| {
+            line.notes
+                .last()
+                .is_some_and(|(_, note)| f64::from(note.end()) > beat)
+        })?;
+        let line: Vec<_> = line
+            .notes
+            .iter()
+            .filter_map(|(note_idx, note)| {
+                Some(Syllable {
+                    text: note.text,
+                    kind: self.note_kind((line.index, *note_idx))?,
+                    start: note.start,
+                    end: note.end(),
+                })
+            })
+            .collect();
+        let current = line
+            .iter()
+            .rposition(|syllable| f64::from(syllable.start) <= beat);
+        let progress = current.map_or(0., |idx| {
+            let syllable = &line[idx];
+            ((beat - f64::from(syllable.start)) / f64::from(syllable.end - syllable.start))
+                .clamp(0., 1.)
+        });
+        Some(LyricCursor {
+            line,
+            current,
+            progress,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::{NoteKind, Song};
+
+    #[test]
+    fn cursor() {
+        let song = Song::from_txt_str(
+            "#TITLE:t\n#ARTIST:a\n#BPM:100\n#GAP:0\n\
+             : 0 2 0 One\nR 2 2 0  two\n- 5\n* 6 2 0 Three\nF 8 2 0  four\nG 10 2 0  five\nE\n",
+        )
+        .unwrap();
+        let before = song.lyric_cursor(0, -1.).unwrap();
+        assert_eq!(before.current, None);
+        let kinds: Vec<_> = before.line.iter().map(|syllable| syllable.kind).collect();
+        assert_eq!(kinds, vec![NoteKind::Normal, NoteKind::Rap]);
+
+        let cursor = song.lyric_cursor(0, 3.).unwrap();
+        assert_eq!(cursor.current, Some(1));
+        assert!((cursor.progress - 0.5).abs() < f64::EPSILON);
+
+        let cursor = song.lyric_cursor(0, 5.).unwrap();
+        assert_eq!(cursor.current, None);
+        let kinds: Vec<_> = cursor.line.iter().map(|syllable| syllable.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![NoteKind::Golden, NoteKind::Freestyle, NoteKind::GoldenRap]
+        );
+        assert!(song.lyric_cursor(0, 12.).is_none());
+    }
+}
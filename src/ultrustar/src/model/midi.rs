@@ -0,0 +1,757 @@
+//! Conversion between `Song`s and Standard MIDI Files
+//!
+//! Import supports `.kar` karaoke files as well as plain MIDI files with lyric meta-events.
+//! Since songs have a fixed tempo, notes are placed according to the absolute time computed
+//! from the MIDI tempo map, using the initial tempo as BPM.
+//!
+//! Export writes a single voice of a song as a melody track with lyric meta-events.
+//! Files exported from a song can be imported again without changing the timing of any note.
+
+use super::{
+    encoding,
+    timing::{Timing, BEATS_PER_QUARTER},
+    NoteKind, Song,
+};
+use anyhow::{anyhow, Result};
+use midly::{
+    num::{u15, u24, u28, u4, u7},
+    Format, MetaMessage, MidiMessage, Smf, Track, TrackEvent, TrackEventKind,
+};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, ops::RangeInclusive};
+use ultrastar_txt::{Header, Line, Note, TXTSong};
+
+/// MIDI key of pitch 0 in songs, i.e. the middle C
+pub(crate) const MIDDLE_C: i32 = 60;
+/// Channel reserved for percussion by General MIDI
+const DRUM_CHANNEL: u8 = 9;
+/// Tempo to assume before the first tempo event (120 quarters per minute)
+const DEFAULT_US_PER_QUARTER: f64 = 500_000.;
+
+/// User choices for MIDI import
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Index of the track containing the melody.
+    ///
+    /// If `None`, the track whose notes best match the lyrics is chosen.
+    pub melody_track: Option<usize>,
+    /// Semitones added to every note
+    pub pitch_offset: i32,
+}
+
+/// User choices for MIDI export
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    /// Resolution of the exported file. Should be a multiple of 4 to keep beats on whole ticks.
+    pub ticks_per_quarter: u16,
+    /// Velocity of regular notes
+    pub velocity: u8,
+    /// Velocity of golden notes, which makes them stand out when played back
+    pub golden_velocity: u8,
+}
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            ticks_per_quarter: 480,
+            velocity: 80,
+            golden_velocity: 127,
+        }
+    }
+}
+
+/// Converts MIDI ticks to seconds
+pub(crate) enum TempoMap {
+    /// Ticks per quarter note and `(tick, microseconds per quarter)` pairs ordered by tick
+    Metrical {
+        ticks_per_quarter: f64,
+        changes: Vec<(u32, f64)>,
+    },
+    /// SMPTE timecode based files have a fixed number of ticks per second
+    Timecode { ticks_per_sec: f64 },
+}
+impl TempoMap {
+    pub(crate) fn from_smf(smf: &Smf) -> Self {
+        match smf.header.timing {
+            midly::Timing::Metrical(ticks_per_quarter) => {
+                let mut changes: Vec<_> = smf
+                    .tracks
+                    .iter()
+                    .flat_map(absolute_ticks)
+                    .filter_map(|(tick, kind)| match kind {
+                        TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                            Some((tick, f64::from(tempo.as_int())))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                changes.sort_by_key(|(tick, _)| *tick);
+                Self::Metrical {
+                    ticks_per_quarter: f64::from(ticks_per_quarter.as_int()),
+                    changes,
+                }
+            }
+            midly::Timing::Timecode(fps, subframes) => Self::Timecode {
+                ticks_per_sec: f64::from(fps.as_f32()) * f64::from(subframes),
+            },
+        }
+    }
+
+    pub(crate) fn secs(&self, tick: u32) -> f64 {
+        match self {
+            Self::Timecode { ticks_per_sec } => f64::from(tick) / ticks_per_sec,
+            Self::Metrical {
+                ticks_per_quarter,
+                changes,
+            } => {
+                let mut secs = 0.;
+                let mut last_tick = 0;
+                let mut us_per_quarter = DEFAULT_US_PER_QUARTER;
+                for &(change_tick, tempo) in changes.iter().take_while(|(t, _)| *t <= tick) {
+                    secs += f64::from(change_tick - last_tick) * us_per_quarter
+                        / 1_000_000.
+                        / ticks_per_quarter;
+                    last_tick = change_tick;
+                    us_per_quarter = tempo;
+                }
+                secs + f64::from(tick - last_tick) * us_per_quarter / 1_000_000. / ticks_per_quarter
+            }
+        }
+    }
+
+    /// Quarter notes per minute at the beginning of the file
+    pub(crate) fn initial_qpm(&self) -> f64 {
+        let us_per_quarter = match self {
+            Self::Metrical { changes, .. } => changes
+                .first()
+                .filter(|(tick, _)| *tick == 0)
+                .map_or(DEFAULT_US_PER_QUARTER, |(_, tempo)| *tempo),
+            Self::Timecode { .. } => DEFAULT_US_PER_QUARTER,
+        };
+        60_000_000. / us_per_quarter
+    }
+}
+
+/// Iterate the events of a track together with their absolute tick
+pub(crate) fn absolute_ticks<'a, 't>(
+    track: &'t Track<'a>,
+) -> impl Iterator<Item = (u32, TrackEventKind<'a>)> + 't {
+    track.iter().scan(0, |tick, event| {
+        *tick += event.delta.as_int();
+        Some((*tick, event.kind))
+    })
+}
+
+/// A note of the melody in MIDI ticks
+#[derive(Debug)]
+pub(crate) struct MidiNote {
+    pub(crate) start: u32,
+    pub(crate) end: u32,
+    pub(crate) key: u8,
+}
+
+/// Extract the notes within `keys` of a track, ignoring percussion.
+///
+/// Since singing is monophonic, a new note ends the one currently playing.
+pub(crate) fn track_notes(track: &Track, keys: RangeInclusive<u8>) -> Vec<MidiNote> {
+    let mut notes = Vec::new();
+    let mut playing: Option<(u32, u8)> = None;
+    for (tick, kind) in absolute_ticks(track) {
+        let (key, on) = match kind {
+            TrackEventKind::Midi { channel, message } if channel.as_int() != DRUM_CHANNEL => {
+                match message {
+                    MidiMessage::NoteOn { key, vel } => (key.as_int(), vel.as_int() > 0),
+                    MidiMessage::NoteOff { key, .. } => (key.as_int(), false),
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+        if !keys.contains(&key) {
+            continue;
+        }
+        match playing {
+            Some((start, playing_key)) if on || playing_key == key => {
+                if tick > start {
+                    notes.push(MidiNote {
+                        start,
+                        end: tick,
+                        key: playing_key,
+                    });
+                }
+                playing = None;
+            }
+            _ => (),
+        }
+        if on {
+            playing = Some((tick, key));
+        }
+    }
+    notes
+}
+
+/// A lyric event
+#[derive(Debug)]
+struct Syllable {
+    tick: u32,
+    text: String,
+    /// Whether a new line starts with this syllable
+    line_break: bool,
+}
+
+/// Metadata found in `.kar` specific text events
+#[derive(Default)]
+struct KarInfos {
+    title: Option<String>,
+    artist: Option<String>,
+    language: Option<String>,
+}
+
+/// Collect lyrics from all tracks.
+///
+/// Proper lyric meta-events are preferred. Otherwise, text events are interpreted following
+/// `.kar` conventions: `@` introduces metadata while `/` and `\` start a new line.
+fn lyrics(smf: &Smf) -> (Vec<Syllable>, KarInfos) {
+    let events = |lyric_events: bool| {
+        smf.tracks
+            .iter()
+            .flat_map(absolute_ticks)
+            .filter_map(move |(tick, kind)| match kind {
+                TrackEventKind::Meta(MetaMessage::Lyric(text)) if lyric_events => {
+                    Some((tick, encoding::decode(text).0))
+                }
+                TrackEventKind::Meta(MetaMessage::Text(text)) if !lyric_events => {
+                    Some((tick, encoding::decode(text).0))
+                }
+                _ => None,
+            })
+    };
+    let mut infos = KarInfos::default();
+    let mut texts: Vec<_> = events(true).collect();
+    if texts.is_empty() {
+        texts = events(false)
+            .filter(|(_, text)| match text.strip_prefix('@') {
+                Some(info) => {
+                    let mut chars = info.chars();
+                    match (chars.next(), chars.as_str()) {
+                        (Some('T'), value) if infos.title.is_none() => {
+                            infos.title = Some(value.into());
+                        }
+                        (Some('T'), value) if infos.artist.is_none() => {
+                            infos.artist = Some(value.into());
+                        }
+                        (Some('L'), value) => infos.language = Some(value.into()),
+                        _ => (),
+                    }
+                    false
+                }
+                None => true,
+            })
+            .collect();
+    }
+    texts.sort_by_key(|(tick, _)| *tick);
+
+    let mut syllables = Vec::new();
+    let mut pending_break = false;
+    for (tick, text) in texts {
+        let line_break = pending_break || text.starts_with(['/', '\\', '\r', '\n']);
+        pending_break = text.ends_with(['\r', '\n']);
+        let text = text.trim_matches(['/', '\\', '\r', '\n']);
+        if text.is_empty() {
+            pending_break |= line_break;
+            continue;
+        }
+        syllables.push(Syllable {
+            tick,
+            text: text.to_owned(),
+            line_break,
+        });
+    }
+    (syllables, infos)
+}
+
+/// Pick the track whose note onsets coincide with the most syllables
+fn find_melody_track(tracks: &[Vec<MidiNote>], syllables: &[Syllable]) -> Option<usize> {
+    tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, notes)| !notes.is_empty())
+        .max_by_key(|(idx, notes)| {
+            let hits = notes
+                .iter()
+                .filter(|note| {
+                    syllables
+                        .binary_search_by_key(&note.start, |syllable| syllable.tick)
+                        .is_ok()
+                })
+                .count();
+            (hits, Reverse(*idx))
+        })
+        .map(|(idx, _)| idx)
+}
+
+/// The name of a track, if it has one
+pub(crate) fn track_name(track: &Track) -> Option<String> {
+    track.iter().find_map(|event| match event.kind {
+        TrackEventKind::Meta(MetaMessage::TrackName(name)) => Some(encoding::decode(name).0),
+        _ => None,
+    })
+}
+
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn round_beat(beat: f64) -> i32 {
+    beat.round() as i32
+}
+
+/// Import a MIDI or `.kar` file
+///
+/// # Errors
+///
+/// If the file cannot be parsed or doesn't contain any notes in the selected track
+pub fn import(bytes: &[u8], options: &ImportOptions) -> Result<Song> {
+    let smf = Smf::parse(bytes)?;
+    let tempo = TempoMap::from_smf(&smf);
+    let (syllables, infos) = lyrics(&smf);
+    let tracks: Vec<_> = smf
+        .tracks
+        .iter()
+        .map(|track| track_notes(track, 0..=127))
+        .collect();
+    let melody_track = match options.melody_track {
+        Some(idx) => idx,
+        None => find_melody_track(&tracks, &syllables)
+            .ok_or_else(|| anyhow!("No track with notes found"))?,
+    };
+    let notes = tracks
+        .get(melody_track)
+        .filter(|notes| !notes.is_empty())
+        .ok_or_else(|| anyhow!("Track {} has no notes", melody_track))?;
+
+    // Assign every syllable to the note it falls into or, if it is off, to the next one
+    let mut texts = vec![(String::new(), false); notes.len()];
+    for syllable in &syllables {
+        let idx = notes.partition_point(|note| note.end <= syllable.tick);
+        if let Some((text, line_break)) = texts.get_mut(idx) {
+            text.push_str(&syllable.text);
+            *line_break |= syllable.line_break;
+        }
+    }
+
+    let bpm = tempo.initial_qpm();
+    // Beat 0 is the first note, which keeps the melody on the beat grid even if it starts off-grid
+    let gap_ms = tempo.secs(notes[0].start) * 1000.;
+    let timing = Timing::new(bpm, gap_ms);
+    let beat = |tick| round_beat(timing.secs_to_beat(tempo.secs(tick)));
+    let mut lines: Vec<Line> = Vec::new();
+    for (note, (text, line_break)) in notes.iter().zip(texts) {
+        let start = beat(note.start);
+        let duration = (beat(note.end) - start).max(1);
+        let pitch = i32::from(note.key) - MIDDLE_C + options.pitch_offset;
+        let text = if text.is_empty() { "~".into() } else { text };
+        let note = Note::Regular {
+            start,
+            duration,
+            pitch,
+            text,
+        };
+        match lines.last_mut() {
+            Some(line) if !line_break => line.notes.push(note),
+            _ => lines.push(Line {
+                start,
+                rel: None,
+                notes: vec![note],
+            }),
+        }
+    }
+
+    let track_name = smf.tracks.first().and_then(track_name);
+    #[allow(clippy::cast_possible_truncation)]
+    let header = Header {
+        title: infos.title.or(track_name).unwrap_or_default(),
+        artist: infos.artist.unwrap_or_default(),
+        bpm: bpm as f32,
+        audio_path: std::path::PathBuf::new(),
+        gap: Some(gap_ms as f32),
+        cover_path: None,
+        background_path: None,
+        video_path: None,
+        video_gap: None,
+        genre: None,
+        edition: None,
+        language: infos.language,
+        year: None,
+        relative: None,
+        unknown: None,
+    };
+    Ok(TXTSong { header, lines }.into())
+}
+
+/// Convert a MIDI or `.kar` file to the contents of a `.txt` song file
+///
+/// # Errors
+///
+/// If importing fails or the result cannot be serialized
+pub fn convert_to_txt(bytes: &[u8], options: &ImportOptions) -> Result<String> {
+    let song = import(bytes, options)?;
+    ultrastar_txt::generate_song_txt(&song.txt.header, &song.txt.lines)
+        .map_err(|err| anyhow!(err.to_string()))
+}
+
+/// A note to be exported in MIDI ticks
+struct ExportNote {
+    start: u32,
+    end: u32,
+    key: u7,
+    vel: u7,
+    lyric: Vec<u8>,
+}
+
+/// Build the track containing `notes` and their lyrics
+fn melody_track(notes: &[ExportNote]) -> Track<'_> {
+    // At the same tick, notes end before the next one starts
+    let channel = u4::new(0);
+    let mut events = Vec::new();
+    for ExportNote {
+        start,
+        end,
+        key,
+        vel,
+        lyric,
+    } in notes
+    {
+        events.push((
+            *end,
+            0,
+            TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOff {
+                    key: *key,
+                    vel: u7::new(0),
+                },
+            },
+        ));
+        events.push((*start, 1, TrackEventKind::Meta(MetaMessage::Lyric(lyric))));
+        events.push((
+            *start,
+            2,
+            TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn {
+                    key: *key,
+                    vel: *vel,
+                },
+            },
+        ));
+    }
+    events.sort_by_key(|(tick, order, _)| (*tick, *order));
+    let mut last_tick = 0;
+    let mut melody: Track = events
+        .into_iter()
+        .map(|(tick, _, kind)| {
+            let delta = tick - last_tick;
+            last_tick = tick;
+            TrackEvent {
+                delta: u28::new(delta),
+                kind,
+            }
+        })
+        .collect();
+    melody.push(TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    melody
+}
+
+/// Export a voice of `song` as a Standard MIDI File
+///
+/// The first track holds the tempo and the title, the second one the notes and lyrics of `voice`.
+/// The end of every line is marked by a carriage return following its last syllable.
+///
+/// # Errors
+///
+/// If the song doesn't have the given voice or contains notes which cannot be represented
+pub fn export(song: &Song, voice: usize, options: &ExportOptions) -> Result<Vec<u8>> {
+    let lines = song.voice_lines(voice);
+    if lines.is_empty() {
+        return Err(anyhow!("Song has no voice {}", voice));
+    }
+    let ticks_per_quarter = u15::try_from(options.ticks_per_quarter)
+        .filter(|ticks| ticks.as_int() > 0)
+        .ok_or_else(|| anyhow!("Invalid resolution {}", options.ticks_per_quarter))?;
+    let timing = song.timing();
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let us_per_quarter = (60_000_000. / timing.bpm()).round() as u32;
+    let us_per_quarter =
+        u24::try_from(us_per_quarter).ok_or_else(|| anyhow!("Invalid BPM {}", timing.bpm()))?;
+    let ticks_per_beat = f64::from(ticks_per_quarter.as_int()) / BEATS_PER_QUARTER;
+    let gap_ticks = timing.gap_ms() * 1000. / f64::from(us_per_quarter.as_int())
+        * f64::from(ticks_per_quarter.as_int());
+    #[allow(clippy::cast_possible_truncation)]
+    let tick = |beat: i32| {
+        let tick = (gap_ticks + f64::from(beat) * ticks_per_beat).round() as i64;
+        u32::try_from(tick).map_err(|_| anyhow!("Beat {} lies before the start of the file", beat))
+    };
+
+    // Lyrics are borrowed by the events, so collect them first
+    let mut notes = Vec::new();
+    for line in lines {
+        let last = line.notes.len() - 1;
+        for (idx, (note_idx, note)) in line.notes.iter().enumerate() {
+            let pitch = note.pitch;
+            let key = u8::try_from(pitch + MIDDLE_C)
+                .ok()
+                .and_then(u7::try_from)
+                .ok_or_else(|| anyhow!("Pitch {} is out of MIDI range", pitch))?;
+            let golden = matches!(
+                song.note_kind((line.index, *note_idx)),
+                Some(NoteKind::Golden | NoteKind::GoldenRap)
+            );
+            let vel = if golden {
+                options.golden_velocity
+            } else {
+                options.velocity
+            };
+            let mut lyric = note.text.as_bytes().to_vec();
+            if idx == last {
+                lyric.push(b'\r');
+            }
+            notes.push(ExportNote {
+                start: tick(note.start)?,
+                end: tick(note.end())?,
+                key,
+                vel: u7::new(vel.min(127)),
+                lyric,
+            });
+        }
+    }
+
+    let melody = melody_track(&notes);
+
+    let title = song.txt.header.title.as_bytes();
+    let meta = |kind| TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(kind),
+    };
+    let mut smf = Smf::new(midly::Header::new(
+        Format::Parallel,
+        midly::Timing::Metrical(ticks_per_quarter),
+    ));
+    smf.tracks.push(vec![
+        meta(MetaMessage::TrackName(title)),
+        meta(MetaMessage::Tempo(us_per_quarter)),
+        meta(MetaMessage::EndOfTrack),
+    ]);
+    smf.tracks.push(melody);
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export, import, ExportOptions, ImportOptions};
+    use midly::{
+        num::{u15, u24, u28, u4, u7},
+        Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+    };
+    use ultrastar_txt::Note;
+
+    const SONG_TXT: &str = "#TITLE:Round Trip
+#ARTIST:Someone
+#MP3:song.ogg
+#BPM:150
+#GAP:1234
+: 0 2 0 Hel
+* 2 2 2 lo
+- 6
+: 8 3 -3  world
+F 12 1 0 ~
+E
+";
+
+    fn event(delta: u32, kind: TrackEventKind) -> TrackEvent {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind,
+        }
+    }
+
+    fn note_on(delta: u32, key: u8, vel: u8) -> TrackEvent<'static> {
+        event(
+            delta,
+            TrackEventKind::Midi {
+                channel: u4::new(0),
+                message: MidiMessage::NoteOn {
+                    key: u7::new(key),
+                    vel: u7::new(vel),
+                },
+            },
+        )
+    }
+
+    #[test]
+    fn kar() {
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::new(96)),
+        ));
+        // 150 quarters per minute
+        smf.tracks.push(vec![
+            event(
+                0,
+                TrackEventKind::Meta(MetaMessage::Tempo(u24::new(400_000))),
+            ),
+            event(0, TrackEventKind::Meta(MetaMessage::Text(b"@TSong"))),
+            event(0, TrackEventKind::Meta(MetaMessage::Text(b"@TArtist"))),
+            event(0, TrackEventKind::Meta(MetaMessage::Text(b"\\Hel"))),
+            event(48, TrackEventKind::Meta(MetaMessage::Text(b"lo"))),
+            event(48, TrackEventKind::Meta(MetaMessage::Text(b"/World"))),
+        ]);
+        smf.tracks.push(vec![
+            note_on(0, 60, 100),
+            note_on(48, 62, 100),
+            note_on(48, 64, 100),
+            note_on(24, 64, 0),
+            note_on(0, 65, 100),
+            note_on(24, 65, 0),
+        ]);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let options = ImportOptions {
+            melody_track: None,
+            pitch_offset: -12,
+        };
+        let song = import(&bytes, &options).unwrap();
+        assert_eq!(song.txt.header.title, "Song");
+        assert_eq!(song.txt.header.artist, "Artist");
+        assert!((song.txt.header.bpm - 150.).abs() < f32::EPSILON);
+        assert_eq!(song.txt.lines.len(), 2);
+        assert_eq!(
+            song.txt.lines[0].notes,
+            vec![
+                Note::Regular {
+                    start: 0,
+                    duration: 2,
+                    pitch: -12,
+                    text: "Hel".into()
+                },
+                Note::Regular {
+                    start: 2,
+                    duration: 2,
+                    pitch: -10,
+                    text: "lo".into()
+                },
+            ]
+        );
+        assert_eq!(
+            song.txt.lines[1].notes,
+            vec![
+                Note::Regular {
+                    start: 4,
+                    duration: 1,
+                    pitch: -8,
+                    text: "World".into()
+                },
+                Note::Regular {
+                    start: 5,
+                    duration: 1,
+                    pitch: -7,
+                    text: "~".into()
+                },
+            ]
+        );
+    }
+
+    /// Note timings in ticks of the melody track of an exported file
+    fn exported_notes(bytes: &[u8]) -> Vec<(u32, u32, u8)> {
+        let smf = Smf::parse(bytes).unwrap();
+        super::track_notes(&smf.tracks[1], 0..=127)
+            .into_iter()
+            .map(|note| (note.start, note.end, note.key))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let song = crate::model::Song::from_txt_str(SONG_TXT).unwrap();
+        let options = ExportOptions::default();
+        let bytes = export(&song, 0, &options).unwrap();
+        assert!(export(&song, 1, &options).is_err());
+
+        // The gap of 1234 ms is rounded to the nearest tick, 400 ms per quarter
+        let first = 1481;
+        assert_eq!(
+            exported_notes(&bytes),
+            vec![
+                (first, first + 240, 60),
+                (first + 240, first + 480, 62),
+                (first + 960, first + 1320, 57),
+                (first + 1440, first + 1560, 60),
+            ]
+        );
+        let smf = Smf::parse(&bytes).unwrap();
+        let velocities: Vec<_> = smf.tracks[1]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { vel, .. },
+                    ..
+                } => Some(vel.as_int()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(velocities, vec![80, 127, 80, 80]);
+
+        let imported = import(&bytes, &ImportOptions::default()).unwrap();
+        assert_eq!(imported.txt.header.title, "Round Trip");
+        assert!((imported.txt.header.bpm - 150.).abs() < f32::EPSILON);
+        assert_eq!(imported.txt.lines.len(), 2);
+        let beats = |song: &crate::model::Song| -> Vec<_> {
+            song.txt
+                .lines
+                .iter()
+                .flat_map(|line| &line.notes)
+                .filter_map(|note| match note {
+                    Note::Regular {
+                        start,
+                        duration,
+                        pitch,
+                        text,
+                    }
+                    | Note::Golden {
+                        start,
+                        duration,
+                        pitch,
+                        text,
+                    }
+                    | Note::Freestyle {
+                        start,
+                        duration,
+                        pitch,
+                        text,
+                    } => Some((*start, *duration, *pitch, text.clone())),
+                    Note::PlayerChange { .. } => None,
+                })
+                .collect()
+        };
+        assert_eq!(beats(&imported), beats(&song));
+        let reexported = export(&imported, 0, &options).unwrap();
+        assert_eq!(exported_notes(&reexported), exported_notes(&bytes));
+    }
+
+    #[test]
+    fn duet_voices() {
+        let song = crate::model::Song::from_txt_str(
+            "#TITLE:Duet\n#ARTIST:Both\n#MP3:a.ogg\n#BPM:150\n#GAP:0\n\
+             P1\n: 0 2 0 One\nP2\n: 0 2 5 Two\n- 4\nP3\n: 4 2 7 Both\nE\n",
+        )
+        .unwrap();
+        let options = ExportOptions::default();
+        let notes = |voice| exported_notes(&export(&song, voice, &options).unwrap());
+        assert_eq!(notes(0), vec![(0, 240, 60), (480, 720, 67)]);
+        assert_eq!(notes(1), vec![(0, 240, 65), (480, 720, 67)]);
+    }
+}
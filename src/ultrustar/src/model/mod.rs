@@ -1,18 +1,271 @@
 #![allow(dead_code)]
 
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+pub mod encoding;
+pub mod highscores;
 pub mod library;
+pub mod lint;
+pub mod lyrics;
+pub mod midi;
+pub mod performance;
+pub mod recording;
+pub mod scoring;
+pub mod sections;
+pub mod statistics;
+pub mod timing;
+pub mod transform;
 pub use library::Library;
+pub use scoring::Score;
+
+/// The different kinds of notes that can be sung
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NoteKind {
+    Normal,
+    Golden,
+    Freestyle,
+    Rap,
+    GoldenRap,
+}
+
+/// Position of a note in a song as `(line index, note index)`
+pub type NotePos = (usize, usize);
 
+/// Replace rap (`R`) and golden rap (`G`) notes, which `ultrastar_txt` doesn't know, by normal and
+/// golden notes.
 ///
-pub type Score = f32;
+/// Returns the converted text and the indices of the rap notes among all note records.
+fn mark_raps(txtstr: &str) -> (String, BTreeSet<usize>) {
+    let mut converted = String::with_capacity(txtstr.len());
+    let mut raps = BTreeSet::new();
+    let mut record = 0;
+    for line in txtstr.lines() {
+        let mut chars = line.chars();
+        let replacement = match chars.next() {
+            Some('R') => Some(':'),
+            Some('G') => Some('*'),
+            _ => None,
+        };
+        match replacement {
+            Some(tag) => {
+                raps.insert(record);
+                converted.push(tag);
+                converted.push_str(chars.as_str());
+            }
+            None => converted.push_str(line),
+        }
+        converted.push('\n');
+        if line.starts_with([':', '*', 'F', 'R', 'G', 'P']) {
+            record += 1;
+        }
+    }
+    (converted, raps)
+}
 
+/// Identifies a song independently of the loader and location it comes from, e.g. to attach
+/// user data to it.
 ///
+/// Consists of artist and title, ignoring case and surrounding whitespace.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SongId(String);
+impl SongId {
+    #[must_use]
+    pub fn from_header(header: &ultrastar_txt::Header) -> Self {
+        Self(format!("{} - {}", header.artist.trim(), header.title.trim()).to_lowercase())
+    }
+}
+
+/// The fields shared by all sung notes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SungNote<'a> {
+    pub start: i32,
+    pub duration: i32,
+    pub pitch: i32,
+    pub text: &'a str,
+}
+impl<'a> SungNote<'a> {
+    /// Returns `None` for player changes
+    #[must_use]
+    pub fn from_note(note: &'a ultrastar_txt::Note) -> Option<Self> {
+        use ultrastar_txt::Note;
+        match note {
+            Note::Regular {
+                start,
+                duration,
+                pitch,
+                text,
+            }
+            | Note::Golden {
+                start,
+                duration,
+                pitch,
+                text,
+            }
+            | Note::Freestyle {
+                start,
+                duration,
+                pitch,
+                text,
+            } => Some(Self {
+                start: *start,
+                duration: *duration,
+                pitch: *pitch,
+                text,
+            }),
+            Note::PlayerChange { .. } => None,
+        }
+    }
+    /// The beat right after the note
+    #[must_use]
+    pub fn end(&self) -> i32 {
+        self.start + self.duration
+    }
+}
+
+/// The notes of a line sung by a single voice
+#[derive(Clone, Debug)]
+pub struct VoiceLine<'a> {
+    /// Index of the line in the song
+    pub index: usize,
+    /// Beat of the line break before the line
+    pub start: i32,
+    /// Notes together with their index in the line
+    pub notes: Vec<(usize, SungNote<'a>)>,
+}
+
+///
+#[derive(Clone)]
 pub struct Song {
     txt: ultrastar_txt::structs::TXTSong,
+    /// Positions of rap notes.
+    ///
+    /// `ultrastar_txt` doesn't know about rap notes, so they are stored as regular or golden notes
+    /// in `txt` and marked here.
+    raps: BTreeSet<NotePos>,
+}
+impl From<ultrastar_txt::TXTSong> for Song {
+    fn from(txt: ultrastar_txt::TXTSong) -> Self {
+        Self {
+            txt,
+            raps: BTreeSet::new(),
+        }
+    }
 }
 impl Song {
-    fn score() -> Score {
-        1.0f32
+    /// Parse a song from the contents of a `.txt` song file
+    ///
+    /// # Errors
+    ///
+    /// If either the header or the song lines fail to parse
+    pub fn from_txt_str(txtstr: &str) -> Result<Self> {
+        let (txtstr, rap_records) = mark_raps(txtstr);
+        let header =
+            ultrastar_txt::parse_txt_header_str(&txtstr).map_err(|err| anyhow!(err.to_string()))?;
+        let lines =
+            ultrastar_txt::parse_txt_lines_str(&txtstr).map_err(|err| anyhow!(err.to_string()))?;
+        let mut song = Self::from(ultrastar_txt::TXTSong { header, lines });
+        // Notes are parsed in the order of their records
+        song.raps = song
+            .txt
+            .lines
+            .iter()
+            .enumerate()
+            .flat_map(|(line_idx, line)| (0..line.notes.len()).map(move |note| (line_idx, note)))
+            .enumerate()
+            .filter(|(record, _)| rap_records.contains(record))
+            .map(|(_, pos)| pos)
+            .collect();
+        Ok(song)
+    }
+    #[must_use]
+    pub fn id(&self) -> SongId {
+        SongId::from_header(&self.txt.header)
+    }
+    #[must_use]
+    pub fn timing(&self) -> timing::Timing {
+        timing::Timing::from_header(&self.txt.header)
+    }
+    /// Value of a header tag which `ultrastar_txt` doesn't know, ignoring ASCII case
+    #[must_use]
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.txt
+            .header
+            .unknown
+            .as_ref()?
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+    /// Look up the kind of the note at `pos`.
+    ///
+    /// Returns `None` if there is no note at `pos` or it is a player change.
+    #[must_use]
+    pub fn note_kind(&self, pos: NotePos) -> Option<NoteKind> {
+        use ultrastar_txt::Note;
+        let rap = self.raps.contains(&pos);
+        let (line, note) = pos;
+        match self.txt.lines.get(line)?.notes.get(note)? {
+            Note::Regular { .. } if rap => Some(NoteKind::Rap),
+            Note::Golden { .. } if rap => Some(NoteKind::GoldenRap),
+            Note::Regular { .. } => Some(NoteKind::Normal),
+            Note::Golden { .. } => Some(NoteKind::Golden),
+            Note::Freestyle { .. } => Some(NoteKind::Freestyle),
+            Note::PlayerChange { .. } => None,
+        }
+    }
+    /// Number of voices, i.e. 2 for duets and 1 otherwise
+    #[must_use]
+    pub fn voice_count(&self) -> usize {
+        let duet = self.txt.lines.iter().flat_map(|line| &line.notes).any(
+            |note| matches!(note, ultrastar_txt::Note::PlayerChange { player } if *player >= 2),
+        );
+        if duet {
+            2
+        } else {
+            1
+        }
+    }
+    /// The lines sung by `voice`, reduced to the notes of that voice.
+    ///
+    /// In duets, player changes `P1`, `P2` and `P3` assign the following notes to the first,
+    /// the second or both voices. Notes before the first player change are sung by both.
+    #[must_use]
+    pub fn voice_lines(&self, voice: usize) -> Vec<VoiceLine<'_>> {
+        use ultrastar_txt::Note;
+        if voice >= self.voice_count() {
+            return vec![];
+        }
+        let mut singers = 0b11;
+        let mut lines = Vec::new();
+        for (index, line) in self.txt.lines.iter().enumerate() {
+            let mut notes = Vec::new();
+            for (note_idx, note) in line.notes.iter().enumerate() {
+                match note {
+                    Note::PlayerChange { player } => {
+                        singers = match player {
+                            1 => 0b01,
+                            2 => 0b10,
+                            _ => 0b11,
+                        };
+                    }
+                    _ if singers & (1 << voice) != 0 => {
+                        notes.extend(SungNote::from_note(note).map(|sung| (note_idx, sung)));
+                    }
+                    _ => (),
+                }
+            }
+            if !notes.is_empty() {
+                lines.push(VoiceLine {
+                    index,
+                    start: line.start,
+                    notes,
+                });
+            }
+        }
+        lines
     }
 }
 
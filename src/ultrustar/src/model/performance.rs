@@ -0,0 +1,232 @@
+//! A song being sung by the players
+//!
+//! Times are seconds on the timeline of the audio inputs. The song starts at `start` on that
+//! timeline, so times within the song are relative to it.
+
+use super::{
+    lyrics::LyricCursor,
+    recording::{Recorder, Recording},
+    scoring::{PitchSample, PlayerOptions, Score},
+    timing::{Offsets, Timing},
+    Song,
+};
+
+/// What a player slot sang
+pub struct PlayerResult {
+    pub voice: usize,
+    pub score: Score,
+    pub recording: Recording,
+}
+
+/// A song being sung, recording the voice of each player slot
+pub struct Performance {
+    song: Song,
+    timing: Timing,
+    /// When the song started
+    start: f64,
+    /// Voice sung by each player slot
+    voices: Vec<usize>,
+    recorders: Vec<Recorder>,
+}
+impl Performance {
+    /// Start singing `song` with the user's `offsets` at `start` with `players` player slots,
+    /// alternating voices in duets. Slots without `options` of their own use the defaults.
+    #[must_use]
+    pub fn new(
+        song: Song,
+        offsets: Offsets,
+        options: &[PlayerOptions],
+        start: f64,
+        players: usize,
+    ) -> Self {
+        let timing = song.timing().with_offsets(offsets);
+        let voices: Vec<_> = (0..players.max(1))
+            .map(|slot| slot % song.voice_count())
+            .collect();
+        let recorders = voices
+            .iter()
+            .enumerate()
+            .map(|(slot, voice)| {
+                let options = options.get(slot).cloned().unwrap_or_default();
+                Recorder::new(&song, *voice, timing, &options)
+            })
+            .collect();
+        Self {
+            song,
+            timing,
+            start,
+            voices,
+            recorders,
+        }
+    }
+
+    #[must_use]
+    pub fn song(&self) -> &Song {
+        &self.song
+    }
+
+    #[must_use]
+    pub fn offsets(&self) -> Offsets {
+        self.timing.offsets()
+    }
+
+    /// Correct the timing while singing, which affects everything from now on
+    pub fn set_offsets(&mut self, offsets: Offsets) {
+        self.timing.set_offsets(offsets);
+        for recorder in &mut self.recorders {
+            recorder.set_timing(self.timing);
+        }
+    }
+
+    /// Record what player `slot` sang, with `sample` timed on the audio timeline
+    pub fn push(&mut self, slot: usize, sample: &PitchSample) {
+        let time = self.elapsed(sample.time);
+        if let Some(recorder) = self.recorders.get_mut(slot) {
+            recorder.push(&PitchSample { time, ..*sample });
+        }
+    }
+
+    /// The current score of player `slot`
+    #[must_use]
+    pub fn score(&self, slot: usize) -> u32 {
+        self.recorders[slot].score().total()
+    }
+
+    /// The octaves player `slot` most recently sang above or below the written notes, if matching
+    /// octave independently
+    #[must_use]
+    pub fn octave_offset(&self, slot: usize) -> Option<i32> {
+        self.recorders[slot].last_octave_offset()
+    }
+
+    /// Number of player slots
+    #[must_use]
+    pub fn players(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// The voice player `slot` sings
+    #[must_use]
+    pub fn voice(&self, slot: usize) -> usize {
+        self.voices[slot]
+    }
+
+    /// Seconds since the start of the song at `now`
+    #[must_use]
+    pub fn elapsed(&self, now: f64) -> f64 {
+        now - self.start
+    }
+
+    /// The lyrics player `slot` sings at `now`
+    #[must_use]
+    pub fn lyric_cursor(&self, slot: usize, now: f64) -> Option<LyricCursor<'_>> {
+        let beat = self.timing.lyrics_beat(self.elapsed(now));
+        self.song.lyric_cursor(self.voice(slot), beat)
+    }
+
+    /// Finish scoring, returning the song and the result of each player slot
+    #[must_use]
+    pub fn finish(self) -> (Song, Vec<PlayerResult>) {
+        let results = self
+            .voices
+            .into_iter()
+            .zip(self.recorders)
+            .map(|(voice, recorder)| {
+                let (score, recording) = recorder.finish();
+                PlayerResult {
+                    voice,
+                    score,
+                    recording,
+                }
+            })
+            .collect();
+        (self.song, results)
+    }
+
+    /// Whether all voices sang their last line at `now`
+    #[must_use]
+    pub fn is_over(&self, now: f64) -> bool {
+        (0..self.players()).all(|slot| self.lyric_cursor(slot, now).is_none())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Performance;
+    use crate::model::{
+        scoring::{
+            test::{samples, written, SONG_TXT},
+            Difficulty, PlayerOptions,
+        },
+        timing::Offsets,
+        Song,
+    };
+
+    #[test]
+    fn lyrics() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let mut performance = Performance::new(song, Offsets::default(), &[], 10., 2);
+        assert_eq!(performance.players(), 2);
+        assert_eq!(performance.voice(1), 0);
+        assert!((performance.elapsed(11.5) - 1.5).abs() < f64::EPSILON);
+        let cursor = performance.lyric_cursor(1, 11.).unwrap();
+        assert_eq!(cursor.current, Some(0));
+        assert!(!performance.is_over(11.));
+        assert!(performance.is_over(20.));
+        // The first note moves by its gap offset
+        performance.set_offsets(Offsets {
+            gap_ms: 500.,
+            ..Offsets::default()
+        });
+        let cursor = performance.lyric_cursor(1, 11.).unwrap();
+        assert_eq!(cursor.current, None);
+        assert!((performance.offsets().gap_ms - 500.).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn record() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let options = [PlayerOptions {
+            difficulty: Difficulty::Hard,
+            ..PlayerOptions::default()
+        }];
+        let mut performance = Performance::new(song, Offsets::default(), &options, 10., 2);
+        // The first player sings along on the audio timeline, the second one is silent
+        for mut sample in samples(written) {
+            sample.time += 10.;
+            performance.push(0, &sample);
+        }
+        assert_eq!(performance.score(0), 10000);
+        assert_eq!(performance.score(1), 0);
+        let (song, results) = performance.finish();
+        assert_eq!(song.id(), results[0].recording.song);
+        assert_eq!(results[0].score.total(), 10000);
+        assert_eq!(results[0].score.difficulty, Difficulty::Hard);
+        assert!(results[1].recording.samples.is_empty());
+        assert_eq!(results[1].score.difficulty, Difficulty::Medium);
+    }
+
+    #[test]
+    fn octaves() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let options = [
+            PlayerOptions::default(),
+            PlayerOptions {
+                octave_independent: true,
+                ..PlayerOptions::default()
+            },
+        ];
+        let mut performance = Performance::new(song, Offsets::default(), &options, 0., 2);
+        // Both players sing an octave low, which only counts for the second one
+        for sample in samples(|t| written(t).map(|pitch| pitch - 12.)) {
+            performance.push(0, &sample);
+            performance.push(1, &sample);
+        }
+        assert_eq!(performance.octave_offset(0), None);
+        assert_eq!(performance.octave_offset(1), Some(-1));
+        let (_, results) = performance.finish();
+        assert_eq!(results[0].score.total(), 0);
+        assert_eq!(results[1].score.total(), 10000);
+        assert_eq!(results[1].score.octave_offset, Some(-1));
+    }
+}
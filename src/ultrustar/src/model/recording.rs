@@ -0,0 +1,214 @@
+//! Recording performances for replaying them later
+//!
+//! A `Recording` holds everything scoring depends on: the samples, the song, the voice, the
+//! player's options and the timing offsets. Samples are quantized before they are scored, so
+//! replaying a recording reproduces the score exactly, e.g. for ghost opponents, bug reports or
+//! regression tests from real performances.
+
+use super::{
+    scoring::{metrics::Metrics, PitchSample, PlayerOptions, Score, Scorer},
+    timing::{Offsets, Timing},
+    Song, SongId,
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Quantized `PitchSample` as `(microseconds, cents, volume)`, the volume scaled to `u16::MAX`.
+///
+/// Serializes as a tuple to keep recordings compact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedSample(i64, Option<i32>, u16);
+impl RecordedSample {
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn quantize(sample: &PitchSample) -> Self {
+        Self(
+            (sample.time * 1e6).round() as i64,
+            sample.pitch.map(|pitch| (pitch * 100.).round() as i32),
+            (sample.volume.clamp(0., 1.) * f32::from(u16::MAX)).round() as u16,
+        )
+    }
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn sample(self) -> PitchSample {
+        let Self(micros, cents, volume) = self;
+        PitchSample {
+            time: micros as f64 / 1e6,
+            pitch: cents.map(|cents| f64::from(cents) / 100.),
+            volume: f32::from(volume) / f32::from(u16::MAX),
+        }
+    }
+}
+
+/// A performance of one voice
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    pub song: SongId,
+    pub voice: usize,
+    pub options: PlayerOptions,
+    /// Offsets at the start of the performance
+    pub offsets: Offsets,
+    /// Offsets changed during the performance, after the given number of samples
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub offset_changes: Vec<(usize, Offsets)>,
+    pub samples: Vec<RecordedSample>,
+}
+impl Recording {
+    /// Score the recorded samples again
+    ///
+    /// # Errors
+    ///
+    /// If `song` is not the recorded song
+    pub fn replay(&self, song: &Song) -> Result<Score> {
+        self.check_song(song)?;
+        let timing = song.timing();
+        let mut scorer = Scorer::new(
+            song,
+            self.voice,
+            timing.with_offsets(self.offsets),
+            &self.options,
+        );
+        let mut changes = self.offset_changes.iter().peekable();
+        for (idx, sample) in self.samples.iter().enumerate() {
+            while let Some((_, offsets)) = changes.next_if(|(at, _)| *at <= idx) {
+                scorer.set_timing(timing.with_offsets(*offsets));
+            }
+            scorer.push(sample.sample());
+        }
+        Ok(scorer.finish())
+    }
+
+    /// Diagnostic metrics of the performance, using the offsets it started with
+    ///
+    /// # Errors
+    ///
+    /// If `song` is not the recorded song
+    pub fn metrics(&self, song: &Song) -> Result<Metrics> {
+        self.check_song(song)?;
+        let samples: Vec<_> = self.samples.iter().map(|sample| sample.sample()).collect();
+        let timing = song.timing().with_offsets(self.offsets);
+        Ok(Metrics::analyze(song, self.voice, &timing, &samples))
+    }
+
+    fn check_song(&self, song: &Song) -> Result<()> {
+        let song_id = song.id();
+        if song_id == self.song {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Recording of {:?} cannot be replayed on {:?}",
+                self.song,
+                song_id
+            ))
+        }
+    }
+}
+
+/// Scores a performance while recording it
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    scorer: Scorer,
+    recording: Recording,
+}
+impl Recorder {
+    /// Start recording `voice` of `song`. The timing may include the user's offsets.
+    #[must_use]
+    pub fn new(song: &Song, voice: usize, timing: Timing, options: &PlayerOptions) -> Self {
+        Self {
+            scorer: Scorer::new(song, voice, timing, options),
+            recording: Recording {
+                song: song.id(),
+                voice,
+                options: options.clone(),
+                offsets: timing.offsets(),
+                offset_changes: Vec::new(),
+                samples: Vec::new(),
+            },
+        }
+    }
+
+    /// The scorer, e.g. to query the current score or take its events
+    pub fn scorer(&mut self) -> &mut Scorer {
+        &mut self.scorer
+    }
+
+    /// The score so far
+    #[must_use]
+    pub fn score(&self) -> &Score {
+        self.scorer.score()
+    }
+
+    /// Like `Scorer::last_octave_offset`
+    #[must_use]
+    pub fn last_octave_offset(&self) -> Option<i32> {
+        self.scorer.last_octave_offset()
+    }
+
+    /// Like `Scorer::set_timing`
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.recording
+            .offset_changes
+            .push((self.recording.samples.len(), timing.offsets()));
+        self.scorer.set_timing(timing);
+    }
+
+    /// Record and score the next sample
+    pub fn push(&mut self, sample: &PitchSample) {
+        let recorded = RecordedSample::quantize(sample);
+        self.recording.samples.push(recorded);
+        self.scorer.push(recorded.sample());
+    }
+
+    /// Finish scoring and return the score together with the recording
+    #[must_use]
+    pub fn finish(self) -> (Score, Recording) {
+        (self.scorer.finish(), self.recording)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Recorder, Recording};
+    use crate::model::{
+        scoring::{
+            test::{samples, written, SONG_TXT},
+            Difficulty, PlayerOptions,
+        },
+        timing::Offsets,
+        Song,
+    };
+
+    #[test]
+    fn replay() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let options = PlayerOptions {
+            difficulty: Difficulty::Hard,
+            ..PlayerOptions::default()
+        };
+        let offsets = Offsets {
+            gap_ms: 30.,
+            ..Offsets::default()
+        };
+        let timing = song.timing().with_offsets(offsets);
+        let mut recorder = Recorder::new(&song, 0, timing, &options);
+        let sampled = samples(|t| written(t - 0.037).map(|pitch| pitch + 0.7 * (t * 40.).sin()));
+        for (idx, sample) in sampled.iter().enumerate() {
+            if idx == 70 {
+                recorder.set_timing(song.timing());
+            }
+            recorder.push(sample);
+        }
+        let (score, recording) = recorder.finish();
+        assert!(score.total() > 0 && score.total() < 10000);
+
+        let json = serde_json::to_string(&recording).unwrap();
+        let parsed: Recording = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, recording);
+        assert_eq!(parsed.offsets, offsets);
+        assert_eq!(parsed.replay(&song).unwrap(), score);
+        assert_eq!(parsed.metrics(&song).unwrap().notes.len(), 3);
+
+        let other = Song::from_txt_str(&SONG_TXT.replace("Someone", "Someone else")).unwrap();
+        assert!(parsed.replay(&other).is_err());
+    }
+}
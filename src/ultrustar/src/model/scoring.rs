@@ -0,0 +1,799 @@
+//! Scoring of performances
+//!
+//! Follows UltraStar Deluxe: a song is worth 10000 points, of which 1000 are a bonus for sung
+//! lines and the rest is split among the beats of all notes. Golden notes are worth twice as
+//! much as normal notes, freestyle notes nothing. Rap notes are scored like normal and golden
+//! notes, except that any voice activity hits them regardless of pitch.
+//!
+//! Every beat is judged by the pitch samples detected while it is sung: it is hit if at least
+//! half of them are within the tolerance of the note's pitch. Each line earns a share of the line
+//! bonus according to the fraction of its points that were hit, which also determines its
+//! `Rating`. Consecutive lines rated at least `Rating::Good` form a combo.
+//!
+//! How close players need to get depends on their `Difficulty`. Off-pitch samples close to the
+//! start or end of a note are ignored, which forgives slightly early or late singing.
+//! Players who cannot reach the written octave may choose to have their pitch compared
+//! regardless of the octave.
+//!
+//! Scoring only depends on the samples and the song timing, so performances can be scored
+//! without any audio hardware.
+
+pub mod metrics;
+
+use super::{midi::MIDDLE_C, timing::Timing, NoteKind, Song};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use tune::note::Note as TuneNote;
+
+/// Points for a perfect performance
+pub const MAX_SCORE: f64 = 10000.;
+/// Points for singing all lines perfectly, which are part of `MAX_SCORE`
+pub const MAX_LINE_BONUS: f64 = 1000.;
+
+/// How precisely notes have to be hit
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tolerances {
+    /// Maximal distance between sung and written pitch in semitones
+    pub semitones: f64,
+    /// Time at the start and end of notes in which off-pitch samples are ignored
+    pub edge_ms: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+    /// Tolerances chosen by the player, e.g. for accessibility
+    Custom(Tolerances),
+}
+impl Difficulty {
+    #[must_use]
+    pub fn tolerances(self) -> Tolerances {
+        match self {
+            Self::Easy => Tolerances {
+                semitones: 2.,
+                edge_ms: 100.,
+            },
+            Self::Medium => Tolerances {
+                semitones: 1.,
+                edge_ms: 60.,
+            },
+            Self::Hard => Tolerances {
+                semitones: 0.,
+                edge_ms: 30.,
+            },
+            Self::Custom(tolerances) => tolerances,
+        }
+    }
+}
+
+/// Scoring choices of a player
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerOptions {
+    pub difficulty: Difficulty,
+    /// Compare pitch classes only, e.g. for men singing parts written for women
+    pub octave_independent: bool,
+}
+
+/// Volume above which rap notes count as being sung
+pub const VOICE_ACTIVITY_VOLUME: f32 = 0.01;
+
+/// Semitones per octave
+const OCTAVE: f64 = 12.;
+
+/// Move `sung` by whole octaves as close as possible to `target`, both as MIDI note numbers
+#[must_use]
+pub fn fold_to_octave(sung: f64, target: f64) -> f64 {
+    sung - ((sung - target) / OCTAVE).round() * OCTAVE
+}
+
+/// Like `fold_to_octave` for notes, e.g. to display a sung note next to the written one
+#[must_use]
+pub fn fold_note(sung: TuneNote, target: TuneNote) -> TuneNote {
+    let shift = (sung.midi_number() - target.midi_number() + 6).div_euclid(12) * 12;
+    TuneNote::from_midi_number(sung.midi_number() - shift)
+}
+
+/// A pitch detected at a point in time
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PitchSample {
+    /// Seconds since the start of the audio
+    pub time: f64,
+    /// Detected pitch as fractional MIDI note number, `None` if nothing was sung
+    pub pitch: Option<f64>,
+    /// RMS of the signal between 0 and 1
+    pub volume: f32,
+}
+
+/// Feedback for a sung line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Rating {
+    Awful,
+    Poor,
+    #[serde(rename = "OK")]
+    Ok,
+    Good,
+    Great,
+    Perfect,
+}
+impl Rating {
+    /// Rate a line of which `fraction` of the points were hit
+    #[must_use]
+    pub fn from_fraction(fraction: f64) -> Self {
+        match fraction {
+            f if f >= 0.95 => Self::Perfect,
+            f if f >= 0.8 => Self::Great,
+            f if f >= 0.6 => Self::Good,
+            f if f >= 0.4 => Self::Ok,
+            f if f >= 0.2 => Self::Poor,
+            _ => Self::Awful,
+        }
+    }
+    /// Whether the line continues a combo
+    #[must_use]
+    pub fn is_good(self) -> bool {
+        self >= Self::Good
+    }
+}
+
+/// Result of a finished line, published as soon as the line is over
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineResult {
+    /// Index of the line in the song
+    pub line: usize,
+    /// Fraction of the line's points that were hit
+    pub fraction: f64,
+    pub rating: Rating,
+    /// Number of consecutive good lines up to and including this one, 0 if this line broke the
+    /// combo
+    pub combo: u32,
+}
+
+/// Number of judged beats and how many of them were hit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HitCount {
+    pub hit: u32,
+    pub judged: u32,
+}
+impl HitCount {
+    /// Fraction of the judged beats that were hit, `None` if none were judged
+    #[must_use]
+    pub fn rate(&self) -> Option<f64> {
+        (self.judged > 0).then(|| f64::from(self.hit) / f64::from(self.judged))
+    }
+    pub fn add(&mut self, other: Self) {
+        self.hit += other.hit;
+        self.judged += other.judged;
+    }
+}
+
+/// `HitCount`s of the kinds of notes that are judged, i.e. all but freestyle notes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KindHits {
+    pub normal: HitCount,
+    pub golden: HitCount,
+    pub rap: HitCount,
+    pub golden_rap: HitCount,
+}
+impl KindHits {
+    #[must_use]
+    pub fn get(&self, kind: NoteKind) -> Option<HitCount> {
+        match kind {
+            NoteKind::Normal => Some(self.normal),
+            NoteKind::Golden => Some(self.golden),
+            NoteKind::Rap => Some(self.rap),
+            NoteKind::GoldenRap => Some(self.golden_rap),
+            NoteKind::Freestyle => None,
+        }
+    }
+    fn get_mut(&mut self, kind: NoteKind) -> Option<&mut HitCount> {
+        match kind {
+            NoteKind::Normal => Some(&mut self.normal),
+            NoteKind::Golden => Some(&mut self.golden),
+            NoteKind::Rap => Some(&mut self.rap),
+            NoteKind::GoldenRap => Some(&mut self.golden_rap),
+            NoteKind::Freestyle => None,
+        }
+    }
+    pub fn add(&mut self, other: &Self) {
+        self.normal.add(other.normal);
+        self.golden.add(other.golden);
+        self.rap.add(other.rap);
+        self.golden_rap.add(other.golden_rap);
+    }
+}
+
+/// Points of a performance
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Score {
+    /// Points for normal notes
+    pub normal: f64,
+    /// Points for golden notes
+    pub golden: f64,
+    pub line_bonus: f64,
+    /// The difficulty the points were achieved on, since scores of different difficulties are not
+    /// comparable
+    pub difficulty: Difficulty,
+    /// With octave independent matching, the octaves most hits were sung above (positive) or below
+    /// (negative) the written notes
+    pub octave_offset: Option<i32>,
+    /// Results of the lines finished so far in the order they were sung
+    pub lines: Vec<LineResult>,
+    /// Beats judged so far by note kind
+    #[serde(default)]
+    pub hits: KindHits,
+}
+impl Score {
+    /// The total as displayed to players
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn total(&self) -> u32 {
+        (self.normal + self.golden + self.line_bonus).round() as u32
+    }
+    /// The longest combo of the performance
+    #[must_use]
+    pub fn max_combo(&self) -> u32 {
+        self.lines.iter().map(|line| line.combo).max().unwrap_or(0)
+    }
+}
+
+/// Relative worth of a beat of a note
+fn beat_value(kind: NoteKind) -> f64 {
+    match kind {
+        NoteKind::Normal | NoteKind::Rap => 1.,
+        NoteKind::Golden | NoteKind::GoldenRap => 2.,
+        NoteKind::Freestyle => 0.,
+    }
+}
+
+/// A note that can earn points
+#[derive(Clone, Debug)]
+struct ScoredNote {
+    kind: NoteKind,
+    start: i32,
+    end: i32,
+    /// Written pitch as MIDI note number
+    key: f64,
+}
+
+#[derive(Clone, Debug)]
+struct ScoredLine {
+    /// Index of the line in the song
+    index: usize,
+    notes: Vec<ScoredNote>,
+    /// Sum of the values of all beats
+    value: f64,
+    /// Sum of the values of the beats hit so far
+    hit: f64,
+}
+
+/// How a sample relates to the note it is sung at
+enum Judgement {
+    Miss,
+    Hit,
+    /// Hit after folding into the octave of the note, which was the given number of octaves away
+    OctaveHit(i32),
+}
+
+/// Position of a beat to be judged as `(line, note, beat)`
+type Cursor = (usize, usize, i32);
+
+/// Scores a single voice from a stream of pitch samples
+#[derive(Clone, Debug)]
+pub struct Scorer {
+    timing: Timing,
+    tolerances: Tolerances,
+    octave_independent: bool,
+    /// Number of hit samples by octave offset, with octave independent matching
+    octave_hits: BTreeMap<i32, u32>,
+    /// Octave offset of the most recent hit sample
+    last_octave_offset: Option<i32>,
+    lines: Vec<ScoredLine>,
+    /// Points of a beat of value 1
+    points_per_value: f64,
+    /// Line bonus for a perfectly sung line
+    points_per_line: f64,
+    /// Next beat to be judged, `None` after the last one
+    cursor: Option<Cursor>,
+    /// Samples which may still fall into the window of a beat to be judged
+    samples: VecDeque<PitchSample>,
+    /// Number of consecutive good lines before the cursor
+    combo: u32,
+    /// Line results not taken by `take_events` yet
+    events: Vec<LineResult>,
+    score: Score,
+}
+impl Scorer {
+    /// Prepare scoring `voice` of `song`. The timing may include the user's offsets.
+    #[must_use]
+    pub fn new(song: &Song, voice: usize, timing: Timing, options: &PlayerOptions) -> Self {
+        let lines: Vec<_> = song
+            .voice_lines(voice)
+            .into_iter()
+            .map(|line| {
+                let notes: Vec<_> = line
+                    .notes
+                    .iter()
+                    .filter_map(|&(note_idx, note)| {
+                        let kind = song.note_kind((line.index, note_idx))?;
+                        (beat_value(kind) > 0. && note.duration > 0).then_some(ScoredNote {
+                            kind,
+                            start: note.start,
+                            end: note.end(),
+                            key: f64::from(note.pitch + MIDDLE_C),
+                        })
+                    })
+                    .collect();
+                let value = notes
+                    .iter()
+                    .map(|note| beat_value(note.kind) * f64::from(note.end - note.start))
+                    .sum();
+                ScoredLine {
+                    index: line.index,
+                    notes,
+                    value,
+                    hit: 0.,
+                }
+            })
+            .filter(|line| line.value > 0.)
+            .collect();
+        let total_value: f64 = lines.iter().map(|line| line.value).sum();
+        #[allow(clippy::cast_precision_loss)]
+        let (points_per_value, points_per_line) = if lines.is_empty() {
+            (0., 0.)
+        } else {
+            (
+                (MAX_SCORE - MAX_LINE_BONUS) / total_value,
+                MAX_LINE_BONUS / lines.len() as f64,
+            )
+        };
+        let cursor = lines.first().map(|line| (0, 0, line.notes[0].start));
+        Self {
+            timing,
+            tolerances: options.difficulty.tolerances(),
+            octave_independent: options.octave_independent,
+            octave_hits: BTreeMap::new(),
+            last_octave_offset: None,
+            lines,
+            points_per_value,
+            points_per_line,
+            cursor,
+            samples: VecDeque::new(),
+            combo: 0,
+            events: Vec::new(),
+            score: Score {
+                difficulty: options.difficulty,
+                ..Score::default()
+            },
+        }
+    }
+
+    /// Change the timing, e.g. after the user adjusted the offsets. Applies to all beats not
+    /// judged yet.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    /// The points collected so far
+    #[must_use]
+    pub fn score(&self) -> &Score {
+        &self.score
+    }
+
+    /// The number of consecutive good lines so far
+    #[must_use]
+    pub fn combo(&self) -> u32 {
+        self.combo
+    }
+
+    /// Take the results of the lines finished since the last call, e.g. to show ratings and combo
+    /// effects
+    pub fn take_events(&mut self) -> Vec<LineResult> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// The octaves the most recent hit was sung above or below the written note, if matching
+    /// octave independently
+    #[must_use]
+    pub fn last_octave_offset(&self) -> Option<i32> {
+        self.last_octave_offset
+    }
+
+    /// Feed the next sample. Samples must be pushed in chronological order.
+    pub fn push(&mut self, sample: PitchSample) {
+        self.samples.push_back(sample);
+        self.judge_until(sample.time);
+    }
+
+    /// Judge the remaining beats and return the final score
+    #[must_use]
+    pub fn finish(mut self) -> Score {
+        self.judge_until(f64::INFINITY);
+        self.score.octave_offset = self
+            .octave_hits
+            .iter()
+            .max_by_key(|(offset, hits)| (**hits, std::cmp::Reverse(offset.abs())))
+            .map(|(offset, _)| *offset);
+        self.score
+    }
+
+    /// Time window in seconds in which a beat is sung
+    fn window(&self, (_, _, beat): Cursor) -> (f64, f64) {
+        (
+            self.timing.beat_to_secs(f64::from(beat)),
+            self.timing.beat_to_secs(f64::from(beat + 1)),
+        )
+    }
+
+    fn judge(&self, note: &ScoredNote, sample: &PitchSample) -> Judgement {
+        match note.kind {
+            NoteKind::Rap | NoteKind::GoldenRap if sample.volume >= VOICE_ACTIVITY_VOLUME => {
+                Judgement::Hit
+            }
+            NoteKind::Rap | NoteKind::GoldenRap => Judgement::Miss,
+            _ => {
+                let Some(sung) = sample.pitch else {
+                    return Judgement::Miss;
+                };
+                let pitch = if self.octave_independent {
+                    fold_to_octave(sung, note.key)
+                } else {
+                    sung
+                };
+                // Detected pitches are rounded to semitones
+                if (pitch - note.key).abs() > self.tolerances.semitones + 0.5 {
+                    Judgement::Miss
+                } else if self.octave_independent {
+                    #[allow(clippy::cast_possible_truncation)]
+                    Judgement::OctaveHit(((sung - pitch) / OCTAVE).round() as i32)
+                } else {
+                    Judgement::Hit
+                }
+            }
+        }
+    }
+
+    /// Judge all beats whose window ends before `time`
+    fn judge_until(&mut self, time: f64) {
+        while let Some(cursor) = self.cursor {
+            let (start, end) = self.window(cursor);
+            if end > time {
+                break;
+            }
+            let (line_idx, note_idx, _) = cursor;
+            let note = &self.lines[line_idx].notes[note_idx];
+            let edge = self.tolerances.edge_ms / 1000.;
+            let edge_start = self.timing.beat_to_secs(f64::from(note.start)) + edge;
+            let edge_end = self.timing.beat_to_secs(f64::from(note.end)) - edge;
+            let kind = note.kind;
+            let (mut matching, mut total) = (0, 0);
+            let mut octave_offsets = Vec::new();
+            for sample in self
+                .samples
+                .iter()
+                .filter(|s| (start..end).contains(&s.time))
+            {
+                match self.judge(note, sample) {
+                    Judgement::Hit => matching += 1,
+                    Judgement::OctaveHit(offset) => {
+                        matching += 1;
+                        octave_offsets.push(offset);
+                    }
+                    Judgement::Miss if (edge_start..edge_end).contains(&sample.time) => (),
+                    Judgement::Miss => continue,
+                }
+                total += 1;
+            }
+            for offset in octave_offsets {
+                *self.octave_hits.entry(offset).or_default() += 1;
+                self.last_octave_offset = Some(offset);
+            }
+            let hit = matching > 0 && 2 * matching >= total;
+            if let Some(count) = self.score.hits.get_mut(kind) {
+                count.add(HitCount {
+                    hit: u32::from(hit),
+                    judged: 1,
+                });
+            }
+            if hit {
+                let value = beat_value(kind);
+                let points = value * self.points_per_value;
+                match kind {
+                    NoteKind::Golden | NoteKind::GoldenRap => self.score.golden += points,
+                    _ => self.score.normal += points,
+                }
+                self.lines[line_idx].hit += value;
+            }
+            self.advance();
+            if let Some(next) = self.cursor {
+                let (next_start, _) = self.window(next);
+                while self.samples.front().is_some_and(|s| s.time < next_start) {
+                    self.samples.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Move the cursor to the next beat, finishing lines on the way
+    fn advance(&mut self) {
+        let Some((line_idx, note_idx, beat)) = self.cursor else {
+            return;
+        };
+        let line = &self.lines[line_idx];
+        self.cursor = if beat + 1 < line.notes[note_idx].end {
+            Some((line_idx, note_idx, beat + 1))
+        } else if let Some(note) = line.notes.get(note_idx + 1) {
+            Some((line_idx, note_idx + 1, note.start))
+        } else {
+            let fraction = line.hit / line.value;
+            self.score.line_bonus += self.points_per_line * fraction;
+            let rating = Rating::from_fraction(fraction);
+            self.combo = if rating.is_good() { self.combo + 1 } else { 0 };
+            let result = LineResult {
+                line: line.index,
+                fraction,
+                rating,
+                combo: self.combo,
+            };
+            self.score.lines.push(result);
+            self.events.push(result);
+            self.lines
+                .get(line_idx + 1)
+                .map(|line| (line_idx + 1, 0, line.notes[0].start))
+        };
+    }
+}
+
+impl Song {
+    /// Score a performance of `voice` from its pitch samples in chronological order
+    #[must_use]
+    pub fn score(
+        &self,
+        voice: usize,
+        options: &PlayerOptions,
+        samples: impl IntoIterator<Item = PitchSample>,
+    ) -> Score {
+        let mut scorer = Scorer::new(self, voice, self.timing(), options);
+        for sample in samples {
+            scorer.push(sample);
+        }
+        scorer.finish()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{
+        Difficulty, HitCount, KindHits, PitchSample, PlayerOptions, Rating, Score, Scorer,
+        Tolerances,
+    };
+    use crate::model::{NoteKind, Song};
+    use tune::note::Note as TuneNote;
+
+    /// 150 BPM, so every beat lasts 0.1 seconds, starting after one second
+    pub(crate) const SONG_TXT: &str = "#TITLE:Score
+#ARTIST:Someone
+#MP3:song.ogg
+#BPM:150
+#GAP:1000
+: 0 2 0 One
+* 2 2 2  two
+- 5
+: 6 4 4 Three
+F 10 2 0  free
+E
+";
+
+    /// Samples every 10 ms from 0.9 to 2.3 seconds, singing `pitch(time)`
+    pub(crate) fn samples(pitch: impl Fn(f64) -> Option<f64>) -> Vec<PitchSample> {
+        (90..230)
+            .map(|centis| {
+                let time = f64::from(centis) / 100.;
+                let pitch = pitch(time);
+                PitchSample {
+                    time,
+                    pitch,
+                    volume: if pitch.is_some() { 0.2 } else { 0. },
+                }
+            })
+            .collect()
+    }
+
+    /// The written pitch at `time` in `SONG_TXT`
+    pub(crate) fn written(time: f64) -> Option<f64> {
+        match time {
+            t if (1.0..1.2).contains(&t) => Some(60.),
+            t if (1.2..1.4).contains(&t) => Some(62.),
+            t if (1.6..2.0).contains(&t) => Some(64.),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn perfect() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let score = song.score(0, &PlayerOptions::default(), samples(written));
+        assert_eq!(score.total(), 10000);
+        // Values: 2 normal beats, 2 golden beats worth 4, 4 normal beats
+        assert!((score.golden - 9000. * 4. / 10.).abs() < 1e-6);
+        assert!((score.line_bonus - 1000.).abs() < 1e-6);
+        assert_eq!(score.hits.normal, HitCount { hit: 6, judged: 6 });
+        assert_eq!(
+            score.hits.get(NoteKind::Golden),
+            Some(HitCount { hit: 2, judged: 2 })
+        );
+        assert_eq!(score.hits.get(NoteKind::Freestyle), None);
+    }
+
+    #[test]
+    fn partial() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        // Silence, a semitone too high and off by more than the tolerance
+        let silence = song.score(0, &PlayerOptions::default(), samples(|_| None));
+        assert_eq!(
+            Score {
+                lines: vec![],
+                hits: KindHits::default(),
+                ..silence
+            },
+            Score::default()
+        );
+        let close = song.score(
+            0,
+            &PlayerOptions::default(),
+            samples(|t| written(t).map(|pitch| pitch + 1.4)),
+        );
+        assert_eq!(close.total(), 10000);
+        // Sing most of every beat two semitones off, without forgiveness at note edges
+        let strict = PlayerOptions {
+            difficulty: Difficulty::Custom(Tolerances {
+                semitones: 1.,
+                edge_ms: 0.,
+            }),
+            ..PlayerOptions::default()
+        };
+        let half = song.score(
+            0,
+            &strict,
+            samples(|t| {
+                written(t).map(|p| {
+                    if (t * 100.).round() % 10. < 6. {
+                        p + 2.
+                    } else {
+                        p
+                    }
+                })
+            }),
+        );
+        assert_eq!(half.total(), 0);
+        // Sing only the second line
+        let second = song.score(
+            0,
+            &PlayerOptions::default(),
+            samples(|t| written(t).filter(|_| t >= 1.5)),
+        );
+        assert_eq!(second.total(), 3600 + 500);
+    }
+
+    #[test]
+    fn difficulty() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let score = |difficulty, pitch: &dyn Fn(f64) -> Option<f64>| {
+            // Between the samples of `samples` to keep clear of note boundaries
+            let samples = (90..230).map(|centis| {
+                let time = (f64::from(centis) + 0.5) / 100.;
+                let pitch = pitch(time);
+                PitchSample {
+                    time,
+                    pitch,
+                    volume: if pitch.is_some() { 0.2 } else { 0. },
+                }
+            });
+            let options = PlayerOptions {
+                difficulty,
+                ..PlayerOptions::default()
+            };
+            song.score(0, &options, samples)
+        };
+        let sharp = |t| written(t).map(|pitch| pitch + 2.);
+        assert_eq!(score(Difficulty::Easy, &sharp).total(), 10000);
+        assert_eq!(score(Difficulty::Medium, &sharp).total(), 0);
+        let late = |t| written(t - 0.07);
+        assert_eq!(score(Difficulty::Easy, &late).total(), 10000);
+        assert_eq!(score(Difficulty::Medium, &late).total(), 10000);
+        // The first beats of all notes are missed, which are worth 4 of 10
+        let hard = score(Difficulty::Hard, &late);
+        assert_eq!(hard.total(), 5400 + 250 + 375);
+        assert_eq!(hard.difficulty, Difficulty::Hard);
+        let custom = Difficulty::Custom(Tolerances {
+            semitones: 3.,
+            edge_ms: 0.,
+        });
+        assert_eq!(score(custom, &sharp).total(), 10000);
+    }
+
+    #[test]
+    fn ratings() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let mut scorer = Scorer::new(&song, 0, song.timing(), &PlayerOptions::default());
+        // Miss the second beat of the first line, which is worth 1 of 6
+        for sample in samples(|t| written(t).filter(|_| (1.0..1.1).contains(&t) || t >= 1.2)) {
+            scorer.push(sample);
+            if sample.time < 1.4 {
+                assert!(scorer.take_events().is_empty());
+            }
+        }
+        let events = scorer.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].line, 0);
+        assert!((events[0].fraction - 5. / 6.).abs() < 1e-9);
+        assert_eq!(events[0].rating, Rating::Great);
+        assert_eq!(events[1].rating, Rating::Perfect);
+        assert_eq!(events[1].combo, 2);
+        let score = scorer.finish();
+        assert_eq!(score.lines, events);
+        assert_eq!(score.max_combo(), 2);
+
+        let broken = song.score(
+            0,
+            &PlayerOptions::default(),
+            samples(|t| written(t).filter(|_| t >= 1.5)),
+        );
+        let ratings: Vec<_> = broken.lines.iter().map(|line| line.rating).collect();
+        assert_eq!(ratings, vec![Rating::Awful, Rating::Perfect]);
+        assert_eq!(broken.lines[0].combo, 0);
+        assert_eq!(broken.max_combo(), 1);
+        assert_eq!(Rating::from_fraction(0.5), Rating::Ok);
+    }
+
+    #[test]
+    fn octave_independent() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let low = samples(|t| written(t).map(|pitch| pitch - 12.));
+        let options = PlayerOptions {
+            octave_independent: true,
+            ..PlayerOptions::default()
+        };
+        let exact = song.score(0, &PlayerOptions::default(), low.clone());
+        assert_eq!(exact.total(), 0);
+        assert_eq!(exact.octave_offset, None);
+        let folded = song.score(0, &options, low);
+        assert_eq!(folded.total(), 10000);
+        assert_eq!(folded.octave_offset, Some(-1));
+        assert!((super::fold_to_octave(83.6, 60.) - 59.6).abs() < 1e-9);
+        assert_eq!(
+            super::fold_note(
+                TuneNote::from_midi_number(45),
+                TuneNote::from_midi_number(64)
+            ),
+            TuneNote::from_midi_number(69)
+        );
+    }
+
+    #[test]
+    fn rap_and_freestyle() {
+        let song =
+            Song::from_txt_str(&SONG_TXT.replace(": 0 2 0", "R 0 2 0").replace('*', "G")).unwrap();
+        // Speaking the first line at any pitch, with some noise after it
+        let rap = |t: f64| (1.0..1.4).contains(&t).then_some(0.3);
+        let spoken = (90..230).map(|centis| {
+            let time = f64::from(centis) / 100.;
+            PitchSample {
+                time,
+                pitch: rap(time).map(|_| 40.),
+                volume: rap(time).unwrap_or(0.005),
+            }
+        });
+        let score = song.score(0, &PlayerOptions::default(), spoken);
+        assert!((score.golden - 3600.).abs() < 1e-6);
+        assert!((score.normal - 1800.).abs() < 1e-6);
+        assert_eq!(score.total(), 5400 + 500);
+        // Freestyle notes aren't worth anything
+        let free = samples(|t| (2.0..2.2).contains(&t).then_some(60.));
+        assert_eq!(song.score(0, &PlayerOptions::default(), free).total(), 0);
+    }
+}
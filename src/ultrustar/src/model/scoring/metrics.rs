@@ -0,0 +1,226 @@
+//! Diagnostic metrics of a performance, which don't affect the score
+//!
+//! They are computed per pitched note from the samples aimed at it, i.e. those within a
+//! semitone of the written pitch. Pitches are folded into the octave of the note first, so the
+//! metrics are meaningful for singers in another octave, too.
+
+use super::{fold_to_octave, PitchSample};
+use crate::model::{midi::MIDDLE_C, timing::Timing, NoteKind, NotePos, Song};
+use serde::{Deserialize, Serialize};
+
+/// Cents from the written pitch below which a sample counts as aimed at a note
+const AIMED_CENTS: f64 = 100.;
+/// How early a note may be started
+const MAX_EARLY_SECS: f64 = 0.25;
+/// Samples needed to judge a note
+const MIN_SAMPLES: usize = 3;
+/// Rates of pitch oscillations that count as vibrato
+const VIBRATO_HZ: std::ops::RangeInclusive<f64> = 3.0..=9.0;
+/// Depth in cents below which pitch oscillations are not considered vibrato
+const MIN_VIBRATO_CENTS: f64 = 15.;
+/// Duration of the samples aimed at a note needed to detect vibrato
+const MIN_VIBRATO_SECS: f64 = 0.25;
+
+/// A periodic oscillation of the pitch
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Vibrato {
+    pub rate_hz: f64,
+    /// Amplitude in cents
+    pub depth_cents: f64,
+}
+
+/// Metrics of a single note
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoteMetrics {
+    pub pos: NotePos,
+    /// Mean deviation from the written pitch, positive if sharp
+    pub cents: f64,
+    /// Standard deviation of the pitch in cents, the lower the more stable
+    pub stability: f64,
+    pub vibrato: Option<Vibrato>,
+    /// Milliseconds the note was started after (positive) or before (negative) it's written
+    pub onset_ms: f64,
+}
+
+/// Metrics of a performance
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Metrics of all notes that were sung, in order
+    pub notes: Vec<NoteMetrics>,
+}
+impl Metrics {
+    /// Analyze the pitch track of a performance of `voice`, given as samples in chronological
+    /// order
+    #[must_use]
+    pub fn analyze(song: &Song, voice: usize, timing: &Timing, samples: &[PitchSample]) -> Self {
+        let mut notes = Vec::new();
+        for line in song.voice_lines(voice) {
+            let mut previous = None;
+            for (note_idx, note) in &line.notes {
+                let pos = (line.index, *note_idx);
+                if !matches!(
+                    song.note_kind(pos),
+                    Some(NoteKind::Normal | NoteKind::Golden)
+                ) {
+                    continue;
+                }
+                let key = f64::from(note.pitch + MIDDLE_C);
+                let start = timing.beat_to_secs(f64::from(note.start));
+                let end = timing.beat_to_secs(f64::from(note.end()));
+                // Singing the same pitch before the note may just be the previous note
+                let earliest = match previous {
+                    Some((pitch, previous_end)) if pitch == note.pitch => previous_end,
+                    _ => start - MAX_EARLY_SECS,
+                };
+                previous = Some((note.pitch, end));
+                notes.extend(analyze_note(pos, key, earliest..end, start, samples));
+            }
+        }
+        Self { notes }
+    }
+
+    fn mean(&self, metric: impl Fn(&NoteMetrics) -> Option<f64>) -> Option<f64> {
+        let values: Vec<_> = self.notes.iter().filter_map(metric).collect();
+        #[allow(clippy::cast_precision_loss)]
+        let count = values.len() as f64;
+        (!values.is_empty()).then(|| values.iter().sum::<f64>() / count)
+    }
+
+    /// Average absolute deviation from the written pitches in cents
+    #[must_use]
+    pub fn mean_cents(&self) -> Option<f64> {
+        self.mean(|note| Some(note.cents.abs()))
+    }
+
+    /// Average standard deviation of the pitch within notes in cents
+    #[must_use]
+    pub fn stability(&self) -> Option<f64> {
+        self.mean(|note| Some(note.stability))
+    }
+
+    /// Average vibrato of the notes sung with vibrato
+    #[must_use]
+    pub fn vibrato(&self) -> Option<Vibrato> {
+        Some(Vibrato {
+            rate_hz: self.mean(|note| Some(note.vibrato?.rate_hz))?,
+            depth_cents: self.mean(|note| Some(note.vibrato?.depth_cents))?,
+        })
+    }
+
+    /// Fraction of the notes sung with vibrato
+    #[must_use]
+    pub fn vibrato_share(&self) -> Option<f64> {
+        self.mean(|note| Some(if note.vibrato.is_some() { 1. } else { 0. }))
+    }
+
+    /// Average onset in milliseconds, positive if late
+    #[must_use]
+    pub fn onset_bias_ms(&self) -> Option<f64> {
+        self.mean(|note| Some(note.onset_ms))
+    }
+}
+
+/// Metrics of the note at `pos` written at `key` and `start`, from the `samples` in `window`
+fn analyze_note(
+    pos: NotePos,
+    key: f64,
+    window: std::ops::Range<f64>,
+    start: f64,
+    samples: &[PitchSample],
+) -> Option<NoteMetrics> {
+    let first = samples.partition_point(|sample| sample.time < window.start);
+    let aimed: Vec<(f64, f64)> = samples[first..]
+        .iter()
+        .take_while(|sample| sample.time < window.end)
+        .filter_map(|sample| {
+            let cents = (fold_to_octave(sample.pitch?, key) - key) * 100.;
+            (cents.abs() < AIMED_CENTS).then_some((sample.time, cents))
+        })
+        .collect();
+    if aimed.len() < MIN_SAMPLES {
+        return None;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let count = aimed.len() as f64;
+    let cents = aimed.iter().map(|(_, cents)| cents).sum::<f64>() / count;
+    let variance = aimed.iter().map(|(_, c)| (c - cents).powi(2)).sum::<f64>() / count;
+    let stability = variance.sqrt();
+    Some(NoteMetrics {
+        pos,
+        cents,
+        stability,
+        vibrato: detect_vibrato(&aimed, cents, stability),
+        onset_ms: (aimed[0].0 - start) * 1000.,
+    })
+}
+
+/// Detect vibrato in `(time, cents)` samples from how often they cross their mean
+fn detect_vibrato(aimed: &[(f64, f64)], mean: f64, deviation: f64) -> Option<Vibrato> {
+    let duration = aimed.last()?.0 - aimed.first()?.0;
+    if duration < MIN_VIBRATO_SECS {
+        return None;
+    }
+    let crossings = aimed
+        .windows(2)
+        .filter(|pair| (pair[0].1 - mean).signum() != (pair[1].1 - mean).signum())
+        .count();
+    #[allow(clippy::cast_precision_loss)]
+    let rate_hz = crossings as f64 / 2. / duration;
+    // The amplitude of a sine is √2 times its standard deviation
+    let depth_cents = deviation * std::f64::consts::SQRT_2;
+    (VIBRATO_HZ.contains(&rate_hz) && depth_cents >= MIN_VIBRATO_CENTS).then_some(Vibrato {
+        rate_hz,
+        depth_cents,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metrics;
+    use crate::model::{
+        scoring::test::{samples, written, SONG_TXT},
+        Song,
+    };
+
+    fn analyze(pitch: impl Fn(f64) -> Option<f64>) -> Metrics {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        Metrics::analyze(&song, 0, &song.timing(), &samples(pitch))
+    }
+
+    #[test]
+    fn intonation() {
+        let sharp = analyze(|t| written(t).map(|pitch| pitch + 0.2));
+        assert_eq!(sharp.notes.len(), 3);
+        assert!((sharp.mean_cents().unwrap() - 20.).abs() < 1e-6);
+        assert!(sharp.stability().unwrap() < 1e-6);
+        assert!(sharp.onset_bias_ms().unwrap().abs() < 1e-6);
+        assert_eq!(sharp.vibrato(), None);
+        // An octave below is still in tune
+        let low = analyze(|t| written(t).map(|pitch| pitch - 12.));
+        assert!(low.mean_cents().unwrap() < 1e-6);
+        assert!(analyze(|_| None).notes.is_empty());
+    }
+
+    #[test]
+    fn onsets() {
+        // Samples are taken every 10 ms
+        let late = analyze(|t| written(t - 0.045));
+        assert!((late.onset_bias_ms().unwrap() - 50.).abs() < 1e-6);
+        let early = analyze(|t| written(t + 0.03));
+        assert!((early.onset_bias_ms().unwrap() + 30.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn vibrato() {
+        let vibrato = analyze(|t| {
+            written(t).map(|pitch| pitch + 0.5 * (std::f64::consts::TAU * 5.5 * t).sin())
+        });
+        // Only the last note is long enough
+        let detected: Vec<_> = vibrato.notes.iter().map(|n| n.vibrato.is_some()).collect();
+        assert_eq!(detected, vec![false, false, true]);
+        let average = vibrato.vibrato().unwrap();
+        assert!((average.rate_hz - 5.5).abs() < 1., "{:?}", average);
+        assert!((average.depth_cents - 50.).abs() < 10., "{:?}", average);
+        assert!(vibrato.stability().unwrap() > 20.);
+    }
+}
@@ -0,0 +1,251 @@
+//! Preview and medley sections of songs
+//!
+//! Songs may specify where the preview in song selection starts with `#PREVIEWSTART` (in seconds)
+//! and which part is sung in medleys with `#MEDLEYSTARTBEAT` and `#MEDLEYENDBEAT`.
+//! Without these tags, the chorus is detected as the longest sequence of lines which is repeated
+//! later on with the same lyrics and melody, unless the song disables this with `#CALCMEDLEY:OFF`.
+
+use super::{timing::Timing, Song, SungNote};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Length of previews which are not taken from the medley section
+pub const PREVIEW_SECS: f64 = 30.;
+/// Minimal number of lines of a detected chorus
+const MIN_CHORUS_LINES: usize = 2;
+
+/// Where a section comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Source {
+    /// Given by header tags
+    Tags,
+    /// Detected chorus
+    Detected,
+    /// Neither tags nor a chorus are available
+    Fallback,
+}
+
+/// A part of a song from `start` up to, but excluding, `end` in beats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub start: i32,
+    pub end: i32,
+    pub source: Source,
+}
+impl Section {
+    /// The section in seconds relative to the start of the audio file
+    #[must_use]
+    pub fn secs(&self, timing: &Timing) -> Range<f64> {
+        timing.beat_to_secs(f64::from(self.start))..timing.beat_to_secs(f64::from(self.end))
+    }
+}
+
+/// What makes two lines the same: their lyrics, ignoring case and punctuation, and their
+/// melody relative to the first note
+#[derive(PartialEq, Eq)]
+struct LineKey {
+    text: String,
+    intervals: Vec<i32>,
+}
+impl LineKey {
+    /// Returns `None` for lines without any lyrics, which must not count as repetitions
+    fn new<'a>(notes: impl Iterator<Item = SungNote<'a>>) -> Option<Self> {
+        let mut text = String::new();
+        let mut intervals = Vec::new();
+        let mut first_pitch = None;
+        for note in notes {
+            text.extend(
+                note.text
+                    .chars()
+                    .filter(|c| c.is_alphanumeric())
+                    .flat_map(char::to_lowercase),
+            );
+            let first_pitch = *first_pitch.get_or_insert(note.pitch);
+            intervals.push(note.pitch - first_pitch);
+        }
+        (!text.is_empty()).then_some(Self { text, intervals })
+    }
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    value.replace(',', ".").parse().ok()
+}
+
+impl Song {
+    /// The part of the song to sing in medleys
+    ///
+    /// Returns `None` if the song neither has medley tags nor a detectable chorus.
+    #[must_use]
+    pub fn medley(&self) -> Option<Section> {
+        let tag = |name| self.tag(name).and_then(|value| value.parse().ok());
+        match (tag("MEDLEYSTARTBEAT"), tag("MEDLEYENDBEAT")) {
+            (Some(start), Some(end)) if start < end => Some(Section {
+                start,
+                end,
+                source: Source::Tags,
+            }),
+            _ if self
+                .tag("CALCMEDLEY")
+                .is_some_and(|value| value.eq_ignore_ascii_case("off")) =>
+            {
+                None
+            }
+            _ => self.detect_chorus(),
+        }
+    }
+
+    /// The part of the song to play in song selection
+    ///
+    /// Starts at `#PREVIEWSTART` if given and at the medley section otherwise. If neither is
+    /// available, the preview starts with the first note.
+    #[must_use]
+    pub fn preview(&self) -> Section {
+        let timing = self.timing();
+        #[allow(clippy::cast_possible_truncation)]
+        let beat_at = |secs| timing.secs_to_beat(secs).floor() as i32;
+        let preview_from = |start_secs: f64, source| Section {
+            start: beat_at(start_secs),
+            end: beat_at(start_secs + PREVIEW_SECS),
+            source,
+        };
+        if let Some(start) = self.tag("PREVIEWSTART").and_then(parse_number) {
+            return preview_from(start, Source::Tags);
+        }
+        if let Some(medley) = self.medley() {
+            return medley;
+        }
+        let first_note = self
+            .txt
+            .lines
+            .iter()
+            .flat_map(|line| &line.notes)
+            .find_map(SungNote::from_note)
+            .map_or(0, |note| note.start);
+        preview_from(timing.beat_to_secs(f64::from(first_note)), Source::Fallback)
+    }
+
+    /// Find the longest sequence of lines of the first voice that is repeated later on
+    fn detect_chorus(&self) -> Option<Section> {
+        let lines: Vec<Vec<SungNote>> = self
+            .voice_lines(0)
+            .into_iter()
+            .map(|line| line.notes.into_iter().map(|(_, note)| note).collect())
+            .collect();
+        let keys: Vec<_> = lines
+            .iter()
+            .map(|notes| LineKey::new(notes.iter().copied()))
+            .collect();
+        let start = |line: usize| lines[line][0].start;
+        let end = |line: usize| lines[line].last().map_or(0, SungNote::end);
+
+        // (first line, number of lines, length in beats)
+        let mut best: Option<(usize, usize, i32)> = None;
+        for first in 0..lines.len() {
+            for repeat in first + 1..lines.len() {
+                let len = (0..repeat - first)
+                    .take_while(|offset| {
+                        repeat + offset < lines.len()
+                            && keys[first + offset].is_some()
+                            && keys[first + offset] == keys[repeat + offset]
+                    })
+                    .count();
+                if len < MIN_CHORUS_LINES {
+                    continue;
+                }
+                let beats = end(first + len - 1) - start(first);
+                if Some(beats) > best.map(|(_, _, best_beats)| best_beats) {
+                    best = Some((first, len, beats));
+                }
+            }
+        }
+        best.map(|(first, len, _)| Section {
+            start: start(first),
+            end: end(first + len - 1),
+            source: Source::Detected,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Section, Source};
+    use crate::model::Song;
+
+    const HEADER: &str = "#TITLE:Sections
+#ARTIST:Someone
+#MP3:song.ogg
+#BPM:150
+#GAP:1000
+";
+    const LINES: &str = ": 0 2 0 First
+: 2 2 2  verse
+- 6
+: 8 2 4 Sing
+: 10 2 5  la
+- 14
+: 16 2 7 Cho
+: 18 2 5 rus
+- 22
+: 24 2 5 Cho,
+: 26 2 3  rus!
+- 30
+: 32 2 0 Second
+: 34 2 2  verse
+- 38
+: 40 2 -2 sing
+: 42 2 -1  LA
+- 46
+: 48 2 1 cho
+: 50 2 -1 rus
+- 54
+: 56 2 -1 Cho
+: 58 2 -3 rus
+E
+";
+
+    fn parse(tags: &str) -> Song {
+        Song::from_txt_str(&format!("{}{}{}", HEADER, tags, LINES)).unwrap()
+    }
+
+    #[test]
+    fn detected_chorus() {
+        // Only the first lines of the verses differ, the rest is repeated a few semitones lower
+        let song = parse("");
+        let chorus = Section {
+            start: 8,
+            end: 28,
+            source: Source::Detected,
+        };
+        assert_eq!(song.medley(), Some(chorus));
+        assert_eq!(song.preview(), chorus);
+        // A beat lasts 0.1 seconds after the gap of one second
+        let secs = chorus.secs(&song.timing());
+        assert!((secs.start - 1.8).abs() < 1e-9 && (secs.end - 3.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tags() {
+        let song = parse("#MEDLEYSTARTBEAT:16\n#MEDLEYENDBEAT:30\n#PREVIEWSTART:2,2\n");
+        assert_eq!(
+            song.medley(),
+            Some(Section {
+                start: 16,
+                end: 30,
+                source: Source::Tags,
+            })
+        );
+        // 30 seconds are 300 beats at 150 BPM
+        assert_eq!(
+            song.preview(),
+            Section {
+                start: 12,
+                end: 312,
+                source: Source::Tags,
+            }
+        );
+        let song = parse("#CALCMEDLEY:OFF\n");
+        assert_eq!(song.medley(), None);
+        assert_eq!(song.preview().source, Source::Fallback);
+        assert_eq!(song.preview().start, 0);
+    }
+}
@@ -0,0 +1,186 @@
+//! Statistics of a user's performances for a stats screen
+//!
+//! `UserStats` are aggregated after each performance from its `Recording` and `Score`, so they
+//! don't depend on keeping the recordings.
+
+use super::{
+    recording::Recording,
+    scoring::{KindHits, Score, MAX_SCORE},
+    Song, SongId,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tune::note::Note as TuneNote;
+
+/// Accuracy of a performance, i.e. its share of the maximal score
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccuracyPoint {
+    /// Seconds since the Unix epoch
+    pub date: u64,
+    /// Between 0 and 1
+    pub accuracy: f64,
+}
+
+/// Aggregated performances of a user
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserStats {
+    /// Number of performances by song
+    pub songs: BTreeMap<SongId, u32>,
+    /// Number of performances by artist
+    pub artists: BTreeMap<String, u32>,
+    /// Total length of all performances
+    pub seconds: f64,
+    /// Accuracy of every performance in chronological order
+    pub history: Vec<AccuracyPoint>,
+    pub hits: KindHits,
+    /// Number of samples by detected MIDI note number
+    pub pitches: BTreeMap<i32, u32>,
+}
+impl UserStats {
+    /// Add a performance of `song` which ended at `date`, as seconds since the Unix epoch
+    pub fn add(&mut self, song: &Song, recording: &Recording, score: &Score, date: u64) {
+        *self.songs.entry(song.id()).or_default() += 1;
+        let artist = song.txt.header.artist.trim();
+        if !artist.is_empty() {
+            *self.artists.entry(artist.to_owned()).or_default() += 1;
+        }
+        if let (Some(first), Some(last)) = (recording.samples.first(), recording.samples.last()) {
+            self.seconds += last.sample().time - first.sample().time;
+        }
+        self.history.push(AccuracyPoint {
+            date,
+            accuracy: f64::from(score.total()) / MAX_SCORE,
+        });
+        self.hits.add(&score.hits);
+        for pitch in recording
+            .samples
+            .iter()
+            .filter_map(|sample| sample.sample().pitch)
+        {
+            #[allow(clippy::cast_possible_truncation)]
+            let key = pitch.round() as i32;
+            *self.pitches.entry(key).or_default() += 1;
+        }
+    }
+
+    /// Number of performances
+    #[must_use]
+    pub fn performances(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Number of different songs sung
+    #[must_use]
+    pub fn songs_sung(&self) -> usize {
+        self.songs.len()
+    }
+
+    #[must_use]
+    pub fn minutes(&self) -> f64 {
+        self.seconds / 60.
+    }
+
+    /// Average accuracy of all performances, `None` before the first one
+    #[must_use]
+    pub fn average_accuracy(&self) -> Option<f64> {
+        #[allow(clippy::cast_precision_loss)]
+        let count = self.history.len() as f64;
+        (!self.history.is_empty())
+            .then(|| self.history.iter().map(|point| point.accuracy).sum::<f64>() / count)
+    }
+
+    /// The `n` most sung artists with their number of performances
+    #[must_use]
+    pub fn top_artists(&self, n: usize) -> Vec<(&str, u32)> {
+        let mut artists: Vec<_> = self
+            .artists
+            .iter()
+            .map(|(artist, count)| (artist.as_str(), *count))
+            .collect();
+        artists.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        artists.truncate(n);
+        artists
+    }
+
+    /// Lowest and highest note sung, ignoring the rarest notes which make up at most `outliers`
+    /// of the samples at either end, e.g. to fold songs into the user's range
+    #[must_use]
+    pub fn pitch_range(&self, outliers: f64) -> Option<(TuneNote, TuneNote)> {
+        let total: u32 = self.pitches.values().sum();
+        let limit = f64::from(total) * outliers;
+        let lowest = first_beyond(self.pitches.iter(), limit)?;
+        let highest = first_beyond(self.pitches.iter().rev(), limit)?;
+        Some((
+            TuneNote::from_midi_number(lowest),
+            TuneNote::from_midi_number(highest),
+        ))
+    }
+}
+
+/// The first pitch at which more than `limit` samples have been counted
+fn first_beyond<'a>(pitches: impl Iterator<Item = (&'a i32, &'a u32)>, limit: f64) -> Option<i32> {
+    let mut counted = 0;
+    for (pitch, count) in pitches {
+        counted += count;
+        if f64::from(counted) > limit {
+            return Some(*pitch);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::UserStats;
+    use crate::model::{
+        recording::Recorder,
+        scoring::{
+            test::{samples, written, SONG_TXT},
+            HitCount, PlayerOptions,
+        },
+        Song,
+    };
+    use tune::note::Note as TuneNote;
+
+    #[test]
+    fn aggregate() {
+        let song = Song::from_txt_str(SONG_TXT).unwrap();
+        let mut stats = UserStats::default();
+        assert_eq!(stats.average_accuracy(), None);
+        assert_eq!(stats.pitch_range(0.), None);
+        for (date, pitch) in [
+            (100, written as fn(f64) -> Option<f64>),
+            (200, |_| Some(48.)),
+        ] {
+            let mut recorder = Recorder::new(&song, 0, song.timing(), &PlayerOptions::default());
+            for sample in samples(pitch) {
+                recorder.push(&sample);
+            }
+            let (score, recording) = recorder.finish();
+            stats.add(&song, &recording, &score, date);
+        }
+        assert_eq!(stats.performances(), 2);
+        assert_eq!(stats.songs_sung(), 1);
+        assert_eq!(stats.top_artists(3), vec![("Someone", 2)]);
+        assert!((stats.seconds - 2. * 1.39).abs() < 1e-9);
+        assert!((stats.average_accuracy().unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(stats.history[1].date, 200);
+        assert_eq!(stats.hits.normal, HitCount { hit: 6, judged: 12 });
+        assert_eq!(
+            stats.pitch_range(0.),
+            Some((
+                TuneNote::from_midi_number(48),
+                TuneNote::from_midi_number(64)
+            ))
+        );
+        // The 40 samples at 64 are less than a fifth
+        assert_eq!(
+            stats.pitch_range(0.2),
+            Some((
+                TuneNote::from_midi_number(48),
+                TuneNote::from_midi_number(62)
+            ))
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Conversion between song beats and playback time
+//!
+//! Notes of a song are placed on a grid of beats. Following UltraStar conventions, `#BPM` counts
+//! quarter notes while a beat is a sixteenth note, so a beat lasts `60 / (4 * BPM)` seconds.
+//! `#GAP` is the position of beat 0 in the audio file in milliseconds, `#VIDEOGAP` the position
+//! in the video at the start of the audio in seconds.
+//!
+//! Users can correct songs which are out of sync with per-song `Offsets` instead of editing them.
+
+use super::SongId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ultrastar_txt::Header;
+
+/// Beats per quarter note, i.e. the factor between `#BPM` and actual beats per minute
+pub const BEATS_PER_QUARTER: f64 = 4.;
+
+/// User corrections of the timing of a song
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Offsets {
+    /// Added to `#GAP`, i.e. positive values move the notes later
+    pub gap_ms: f64,
+    /// Added to `#VIDEOGAP`
+    pub video_gap_ms: f64,
+    /// Delay of the displayed lyrics relative to the notes
+    pub lyrics_delay_ms: f64,
+}
+impl Offsets {
+    #[must_use]
+    pub fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The `Offsets` a user has set, by song
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SongOffsets(BTreeMap<SongId, Offsets>);
+impl SongOffsets {
+    /// Offsets for `song`, which are zero unless set before
+    #[must_use]
+    pub fn get(&self, song: &SongId) -> Offsets {
+        self.0.get(song).copied().unwrap_or_default()
+    }
+
+    /// Set the offsets for `song`, forgetting about it if they are zero
+    pub fn set(&mut self, song: SongId, offsets: Offsets) {
+        if offsets.is_zero() {
+            self.0.remove(&song);
+        } else {
+            self.0.insert(song, offsets);
+        }
+    }
+}
+
+/// Maps beats to seconds relative to the start of the audio file and back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    bpm: f64,
+    gap_ms: f64,
+    video_gap_ms: f64,
+    offsets: Offsets,
+}
+impl Timing {
+    #[must_use]
+    pub fn new(bpm: f64, gap_ms: f64) -> Self {
+        Self {
+            bpm,
+            gap_ms,
+            video_gap_ms: 0.,
+            offsets: Offsets::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn from_header(header: &Header) -> Self {
+        Self {
+            video_gap_ms: f64::from(header.video_gap.unwrap_or_default()) * 1000.,
+            ..Self::new(
+                f64::from(header.bpm),
+                f64::from(header.gap.unwrap_or_default()),
+            )
+        }
+    }
+
+    #[must_use]
+    pub fn with_offsets(mut self, offsets: Offsets) -> Self {
+        self.offsets = offsets;
+        self
+    }
+
+    /// Replace the offsets, which affects all conversions from now on
+    pub fn set_offsets(&mut self, offsets: Offsets) {
+        self.offsets = offsets;
+    }
+
+    #[must_use]
+    pub fn offsets(&self) -> Offsets {
+        self.offsets
+    }
+
+    #[must_use]
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    /// The gap including its offset
+    #[must_use]
+    pub fn gap_ms(&self) -> f64 {
+        self.gap_ms + self.offsets.gap_ms
+    }
+
+    /// Duration of a single beat in seconds
+    #[must_use]
+    pub fn beat_duration(&self) -> f64 {
+        60. / (BEATS_PER_QUARTER * self.bpm)
+    }
+
+    #[must_use]
+    pub fn beat_to_secs(&self, beat: f64) -> f64 {
+        self.gap_ms() / 1000. + beat * self.beat_duration()
+    }
+
+    #[must_use]
+    pub fn secs_to_beat(&self, secs: f64) -> f64 {
+        (secs - self.gap_ms() / 1000.) / self.beat_duration()
+    }
+
+    /// The position in the video corresponding to `secs` in the audio
+    #[must_use]
+    pub fn video_secs(&self, secs: f64) -> f64 {
+        secs + (self.video_gap_ms + self.offsets.video_gap_ms) / 1000.
+    }
+
+    /// The beat whose lyrics are displayed at `secs`
+    #[must_use]
+    pub fn lyrics_beat(&self, secs: f64) -> f64 {
+        self.secs_to_beat(secs - self.offsets.lyrics_delay_ms / 1000.)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Offsets, SongOffsets};
+    use crate::model::Song;
+
+    #[test]
+    fn offsets() {
+        let song = Song::from_txt_str(
+            "#TITLE:Late\n#ARTIST:Someone\n#MP3:a.ogg\n#BPM:150\n#GAP:1000\n#VIDEOGAP:2\n: 0 1 0 a\nE\n",
+        )
+        .unwrap();
+        let mut store = SongOffsets::default();
+        let offsets = Offsets {
+            gap_ms: 250.,
+            video_gap_ms: -500.,
+            lyrics_delay_ms: 100.,
+        };
+        store.set(song.id(), offsets);
+        let mut store: SongOffsets =
+            serde_json::from_str(&serde_json::to_string(&store).unwrap()).unwrap();
+        assert_eq!(store.get(&song.id()), offsets);
+
+        let mut timing = song.timing();
+        assert!((timing.beat_to_secs(10.) - 2.).abs() < 1e-9);
+        // Changes apply to the running timing right away
+        timing.set_offsets(store.get(&song.id()));
+        assert!((timing.beat_to_secs(10.) - 2.25).abs() < 1e-9);
+        assert!((timing.video_secs(1.) - 2.5).abs() < 1e-9);
+        assert!((timing.lyrics_beat(2.35) - 10.).abs() < 1e-9);
+
+        store.set(song.id(), Offsets::default());
+        assert!(store.0.is_empty());
+        assert_eq!(timing, song.timing().with_offsets(offsets));
+    }
+}
@@ -0,0 +1,318 @@
+//! Transformations of songs, e.g. for practice modes and accessibility settings
+//!
+//! All transformations leave the original song untouched and return a new one.
+
+use super::{NoteKind, Song, SungNote};
+use std::collections::BTreeSet;
+use tune::note::Note as TuneNote;
+use ultrastar_txt::{Line, Note};
+
+/// Semitones per octave
+const OCTAVE: i32 = 12;
+
+/// Copy of a sung note with new timing and pitch, keeping its type and text
+fn rebuild(note: &Note, start: i32, duration: i32, pitch: i32) -> Note {
+    match note {
+        Note::Regular { text, .. } => Note::Regular {
+            start,
+            duration,
+            pitch,
+            text: text.clone(),
+        },
+        Note::Golden { text, .. } => Note::Golden {
+            start,
+            duration,
+            pitch,
+            text: text.clone(),
+        },
+        Note::Freestyle { text, .. } => Note::Freestyle {
+            start,
+            duration,
+            pitch,
+            text: text.clone(),
+        },
+        Note::PlayerChange { player } => Note::PlayerChange { player: *player },
+    }
+}
+
+/// Change the pitch of a sung note, leaving player changes alone
+fn with_pitch(note: &Note, pitch: impl FnOnce(i32) -> i32) -> Note {
+    match SungNote::from_note(note) {
+        Some(sung) => rebuild(note, sung.start, sung.duration, pitch(sung.pitch)),
+        None => note.clone(),
+    }
+}
+
+/// Whether a note continues the syllable of the previous one
+fn is_continuation(note: &SungNote) -> bool {
+    note.text.trim() == "~"
+}
+
+/// Octave shift moving `pitch` into `lowest..=highest`, or as close as possible
+fn fold_shift(pitch: i32, lowest: i32, highest: i32) -> i32 {
+    if pitch < lowest {
+        (lowest - pitch + OCTAVE - 1) / OCTAVE * OCTAVE
+    } else if pitch > highest {
+        -((pitch - highest + OCTAVE - 1) / OCTAVE * OCTAVE)
+    } else {
+        0
+    }
+}
+
+/// A note of a simplified line together with what it was merged from
+struct Merged<'a> {
+    note: &'a Note,
+    kind: Option<NoteKind>,
+    start: i32,
+    end: i32,
+    /// Pitch and duration of the longest note merged so far
+    longest: (i32, i32),
+    /// Whether all merged notes are shorter than the minimal duration
+    fast: bool,
+}
+
+impl Song {
+    /// Move every note by `semitones`
+    #[must_use]
+    pub fn transpose(&self, semitones: i32) -> Self {
+        self.map_notes(|note| with_pitch(note, |pitch| pitch + semitones))
+    }
+
+    /// Fold notes into the range a singer can reach by shifting them by whole octaves.
+    ///
+    /// To keep the melody intact, every line is moved by the octave that puts most of it into
+    /// the range. Notes still outside are folded individually. If the range is smaller than an
+    /// octave, notes may end up above it.
+    #[must_use]
+    pub fn fold_octaves(&self, lowest: TuneNote, highest: TuneNote) -> Self {
+        let lowest = lowest.midi_number() - super::midi::MIDDLE_C;
+        let highest = (highest.midi_number() - super::midi::MIDDLE_C).max(lowest);
+        let mut song = self.clone();
+        for line in &mut song.txt.lines {
+            let pitches: Vec<_> = line
+                .notes
+                .iter()
+                .filter_map(SungNote::from_note)
+                .map(|note| note.pitch)
+                .collect();
+            let Some(&first) = pitches.first() else {
+                continue;
+            };
+            let distance = |shift: i32| -> i32 {
+                pitches
+                    .iter()
+                    .map(|pitch| {
+                        let pitch = pitch + shift;
+                        (lowest - pitch).max(pitch - highest).max(0)
+                    })
+                    .sum()
+            };
+            let around = fold_shift(first, lowest, highest);
+            let line_shift = [around - OCTAVE, around, around + OCTAVE]
+                .into_iter()
+                .min_by_key(|shift| (distance(*shift), shift.abs()))
+                .unwrap_or(around);
+            for note in &mut line.notes {
+                *note = with_pitch(note, |pitch| {
+                    let pitch = pitch + line_shift;
+                    pitch + fold_shift(pitch, lowest, highest)
+                });
+            }
+        }
+        song
+    }
+
+    /// Merge fast melismas into single notes, e.g. for an easy mode.
+    ///
+    /// Continuations (`~`) of a syllable are merged into the previous note if both are shorter
+    /// than `min_duration` beats and of the same kind. The merged note lasts until the end of
+    /// the last continuation and takes the pitch of the longest note.
+    #[must_use]
+    pub fn simplify_melismas(&self, min_duration: i32) -> Self {
+        let mut raps = BTreeSet::new();
+        let mut lines = Vec::with_capacity(self.txt.lines.len());
+        for (line_idx, line) in self.txt.lines.iter().enumerate() {
+            let mut merged: Vec<Merged> = Vec::new();
+            for (note_idx, note) in line.notes.iter().enumerate() {
+                let kind = self.note_kind((line_idx, note_idx));
+                let Some(sung) = SungNote::from_note(note) else {
+                    merged.push(Merged {
+                        note,
+                        kind,
+                        start: 0,
+                        end: 0,
+                        longest: (0, 0),
+                        fast: false,
+                    });
+                    continue;
+                };
+                let fast = sung.duration < min_duration;
+                match merged.last_mut() {
+                    Some(last)
+                        if fast && last.fast && last.kind == kind && is_continuation(&sung) =>
+                    {
+                        last.end = sung.end();
+                        if sung.duration > last.longest.1 {
+                            last.longest = (sung.pitch, sung.duration);
+                        }
+                    }
+                    _ => merged.push(Merged {
+                        note,
+                        kind,
+                        start: sung.start,
+                        end: sung.end(),
+                        longest: (sung.pitch, sung.duration),
+                        fast,
+                    }),
+                }
+            }
+            let notes = merged
+                .iter()
+                .enumerate()
+                .map(|(note_idx, merged)| {
+                    if matches!(merged.kind, Some(NoteKind::Rap | NoteKind::GoldenRap)) {
+                        raps.insert((line_idx, note_idx));
+                    }
+                    match merged.kind {
+                        Some(_) => rebuild(
+                            merged.note,
+                            merged.start,
+                            merged.end - merged.start,
+                            merged.longest.0,
+                        ),
+                        None => merged.note.clone(),
+                    }
+                })
+                .collect();
+            lines.push(Line {
+                start: line.start,
+                rel: line.rel,
+                notes,
+            });
+        }
+        let mut song = self.clone();
+        song.txt.lines = lines;
+        song.raps = raps;
+        song
+    }
+
+    /// Turn golden notes into normal ones, keeping rap notes as rap
+    #[must_use]
+    pub fn without_golden(&self) -> Self {
+        self.map_notes(|note| match note {
+            Note::Golden {
+                start,
+                duration,
+                pitch,
+                text,
+            } => Note::Regular {
+                start: *start,
+                duration: *duration,
+                pitch: *pitch,
+                text: text.clone(),
+            },
+            note => note.clone(),
+        })
+    }
+
+    /// Turn rap notes into normal ones, keeping golden rap notes as golden
+    #[must_use]
+    pub fn without_raps(&self) -> Self {
+        let mut song = self.clone();
+        song.raps.clear();
+        song
+    }
+
+    /// Apply `f` to every note, keeping the positions of rap notes
+    fn map_notes(&self, f: impl Fn(&Note) -> Note) -> Self {
+        let mut song = self.clone();
+        for note in song.txt.lines.iter_mut().flat_map(|line| &mut line.notes) {
+            *note = f(note);
+        }
+        song
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::{NoteKind, Song, SungNote};
+    use tune::note::Note as TuneNote;
+
+    const SONG_TXT: &str = "#TITLE:Transform
+#ARTIST:Someone
+#MP3:song.ogg
+#BPM:150
+#GAP:1000
+: 0 1 0 Ah
+: 1 1 2 ~
+: 2 1 4 ~
+: 3 4 5 ~
+* 8 1 7 oh
+* 9 2 9 ~
+- 12
+: 12 2 -14 Down
+R 14 2 -9  low
+E
+";
+
+    fn notes(song: &Song) -> Vec<Vec<(i32, i32, i32)>> {
+        song.txt
+            .lines
+            .iter()
+            .map(|line| {
+                line.notes
+                    .iter()
+                    .filter_map(SungNote::from_note)
+                    .map(|note| (note.start, note.duration, note.pitch))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn song() -> Song {
+        Song::from_txt_str(SONG_TXT).unwrap()
+    }
+
+    #[test]
+    fn transpose_and_fold() {
+        let song = song();
+        let transposed = song.transpose(-3);
+        assert_eq!(notes(&transposed)[1], vec![(12, 2, -17), (14, 2, -12)]);
+        assert_eq!(notes(&song)[1], vec![(12, 2, -14), (14, 2, -9)]);
+        assert_eq!(transposed.note_kind((1, 1)), Some(NoteKind::Rap));
+
+        // From the A below the middle C to the C one octave above it
+        let folded = song.fold_octaves(
+            TuneNote::from_midi_number(57),
+            TuneNote::from_midi_number(72),
+        );
+        assert_eq!(notes(&folded)[0], notes(&song)[0]);
+        assert_eq!(notes(&folded)[1], vec![(12, 2, -2), (14, 2, 3)]);
+    }
+
+    #[test]
+    fn simplify() {
+        let simplified = song().simplify_melismas(2);
+        assert_eq!(
+            notes(&simplified),
+            vec![
+                vec![(0, 3, 0), (3, 4, 5), (8, 1, 7), (9, 2, 9)],
+                vec![(12, 2, -14), (14, 2, -9)],
+            ]
+        );
+        assert_eq!(simplified.note_kind((1, 1)), Some(NoteKind::Rap));
+        assert_eq!(simplified.note_kind((0, 2)), Some(NoteKind::Golden));
+    }
+
+    #[test]
+    fn strip_markers() {
+        let song = song();
+        let plain = song.without_golden().without_raps();
+        assert_eq!(notes(&plain), notes(&song));
+        assert_eq!(plain.note_kind((0, 4)), Some(NoteKind::Normal));
+        assert_eq!(plain.note_kind((1, 1)), Some(NoteKind::Normal));
+        assert_eq!(song.note_kind((0, 4)), Some(NoteKind::Golden));
+        assert_eq!(song.note_kind((1, 1)), Some(NoteKind::Rap));
+        assert_eq!(song.note_kind((1, 0)), Some(NoteKind::Normal));
+    }
+}
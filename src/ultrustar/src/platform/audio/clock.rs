@@ -0,0 +1,91 @@
+//! Alignment of capture streams to a common timeline
+//!
+//! Counting samples only measures time within a single stream, and the clocks of devices drift
+//! apart from each other and from the shared timeline. Each stream therefore keeps measuring
+//! when its frames are captured on the timeline shared by all streams, so detections of
+//! different devices can be scored together. Measurements are smoothed, since callbacks don't
+//! run exactly when frames are captured.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Maps positions within a stream to the shared timeline
+#[derive(Debug)]
+pub struct StreamClock(AtomicU64);
+impl StreamClock {
+    /// Bits of a NaN, which are never recorded
+    const UNSET: u64 = u64::MAX;
+    /// Weight of each measurement, about a second of callbacks to follow drift
+    const SMOOTHING: f64 = 0.01;
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self(AtomicU64::new(Self::UNSET))
+    }
+
+    /// Record that the frame `elapsed` seconds into the stream, counting frames at the nominal
+    /// rate, was captured at `secs` on the shared timeline. Doesn't block or allocate, so it may
+    /// run in audio callbacks, but only a single callback may record.
+    pub fn record(&self, elapsed: f64, secs: f64) {
+        let measured = secs - elapsed;
+        let offset = self.offset().map_or(measured, |offset| {
+            offset + (measured - offset) * Self::SMOOTHING
+        });
+        self.0.store(offset.to_bits(), Ordering::Release);
+    }
+
+    /// Time on the shared timeline at the start of the stream, `None` before the first record
+    #[must_use]
+    pub fn offset(&self) -> Option<f64> {
+        match self.0.load(Ordering::Acquire) {
+            Self::UNSET => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+
+    /// Time on the shared timeline after the first `samples` of the stream at `rate`
+    #[must_use]
+    pub fn time(&self, samples: u64, rate: u32) -> Option<f64> {
+        #[allow(clippy::cast_precision_loss)]
+        let elapsed = samples as f64 / f64::from(rate);
+        Some(self.offset()? + elapsed)
+    }
+}
+impl Default for StreamClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StreamClock;
+
+    #[test]
+    fn align() {
+        let clock = StreamClock::new();
+        assert_eq!(clock.time(100, 1000), None);
+        clock.record(0., 2.5);
+        assert_eq!(clock.offset(), Some(2.5));
+        assert!((clock.time(1500, 1000).unwrap() - 4.).abs() < 1e-9);
+        let other = StreamClock::new();
+        other.record(0., 3.);
+        assert!((other.time(1000, 1000).unwrap() - 4.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn drift() {
+        // Callbacks of 10 ms at a device rate 100 ppm below the nominal one, with timestamps
+        // jittering by 1 ms
+        let clock = StreamClock::new();
+        let mut secs = 0.;
+        for callback in 0..2000_u32 {
+            let elapsed = f64::from(callback) * 0.01;
+            secs = elapsed * 1.0001;
+            let jitter = (f64::from(callback % 3) - 1.) * 0.001;
+            clock.record(elapsed, secs + jitter);
+        }
+        // Without resyncing, the error would be 2 ms
+        let error = clock.time(1999 * 160, 16000).unwrap() - secs;
+        assert!(error.abs() < 0.0005, "{}", error);
+    }
+}
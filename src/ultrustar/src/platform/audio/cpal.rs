@@ -1,9 +1,24 @@
-use std::sync::{atomic::AtomicBool, Arc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 
-use super::Note;
+use super::{
+    clock::StreamClock,
+    pipeline::{CaptureSample, Converter, Layout, ANALYSIS_RATE},
+    pitch::Detector,
+    ring::{self, Producer, Window},
+    Detection,
+};
 use anyhow::{anyhow, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use log::{error, info};
+use instant::Instant;
+use log::{error, info, warn};
 use serde::{
     de::{Unexpected, Visitor},
     Deserialize, Deserializer, Serialize,
@@ -72,112 +87,107 @@ pub struct InitSettings {
 }
 impl crate::SettingsTrait for InitSettings {}
 
-#[derive(Debug)]
-enum SampleBuf {
-    U16(Vec<u16>),
-    I16(Vec<i16>),
-    F32(Vec<f32>),
-}
-impl SampleBuf {
-    fn from_sample_format(format: cpal::SampleFormat) -> Self {
-        match format {
-            cpal::SampleFormat::I16 => Self::I16(Vec::new()),
-            cpal::SampleFormat::U16 => Self::U16(Vec::new()),
-            cpal::SampleFormat::F32 => Self::F32(Vec::new()),
-        }
-    }
-}
-trait SampleBufSource {
-    fn copy_to(&self, buf: &mut SampleBuf);
-}
-impl SampleBufSource for [i16] {
-    fn copy_to(&self, buf: &mut SampleBuf) {
-        if let SampleBuf::I16(buf) = buf {
-            buf.clear();
-            buf.extend_from_slice(self);
-        } else {
-            panic!("Using wrong buffer type");
-        }
-    }
-}
-impl SampleBufSource for [u16] {
-    fn copy_to(&self, buf: &mut SampleBuf) {
-        if let SampleBuf::U16(buf) = buf {
-            buf.clear();
-            buf.extend_from_slice(self);
-        } else {
-            panic!("Using wrong buffer type");
-        }
-    }
-}
-impl SampleBufSource for [f32] {
-    fn copy_to(&self, buf: &mut SampleBuf) {
-        if let SampleBuf::F32(buf) = buf {
-            buf.clear();
-            buf.extend_from_slice(self);
-        } else {
-            panic!("Using wrong buffer type");
-        }
-    }
-}
+/// Capacity of the rings between the audio callback and the analysis in seconds
+const RING_SECS: u32 = 1;
 
-#[allow(clippy::type_repetition_in_bounds)] // FIXME wtf?
+/// Converts captured frames of `layout` at `rate` into the signals whose output is `open`, and
+/// records when each callback's frames were captured relative to `epoch`
 fn make_stream_callback<T>(
-    samples_in: crossbeam_channel::Sender<SampleBuf>,
-    samples_out: crossbeam_channel::Receiver<SampleBuf>,
+    mut outputs: Vec<(Converter, Producer)>,
+    open: Arc<[AtomicBool]>,
+    layout: Layout,
+    rate: u32,
+    clock: Arc<StreamClock>,
+    epoch: Instant,
 ) -> impl FnMut(&[T], &cpal::InputCallbackInfo)
 where
-    T: cpal::Sample,
-    [T]: SampleBufSource,
+    T: cpal::Sample + CaptureSample,
 {
-    move |samples: &[T], _info| {
-        let mut buf = samples_out
-            .try_recv()
-            .expect("Input stream should always be able to write");
-        samples.copy_to(&mut buf);
-        samples_in
-            .send(buf)
-            .expect("Audio stream should outlive receiver");
+    let mut frames = 0_u64;
+    move |samples: &[T], info| {
+        let timestamp = info.timestamp();
+        let latency = timestamp
+            .callback
+            .duration_since(&timestamp.capture)
+            .unwrap_or_default();
+        #[allow(clippy::cast_precision_loss)]
+        let elapsed = frames as f64 / f64::from(rate);
+        clock.record(
+            elapsed,
+            epoch.elapsed().saturating_sub(latency).as_secs_f64(),
+        );
+        frames += (samples.len() / usize::from(layout.channels())) as u64;
+        for ((converter, producer), open) in outputs.iter_mut().zip(open.iter()) {
+            if open.load(Ordering::Relaxed) {
+                converter.convert(samples, |sample| {
+                    producer.push(sample);
+                });
+            } else {
+                producer.skip(converter.skip(samples.len()));
+            }
+        }
     }
 }
 
-pub struct NoteInput {
+/// The capture stream of a device, shared by the inputs of its channels
+struct DeviceStream {
     _dev: cpal::Device,
     _stream: cpal::Stream,
-    samples_in: crossbeam_channel::Sender<SampleBuf>,
-    samples_out: crossbeam_channel::Receiver<SampleBuf>,
+    layout: Layout,
     stream_err: Arc<AtomicBool>,
+    clock: Arc<StreamClock>,
+    /// How much the converted signals lag behind the captured frames, in seconds
+    delay: f64,
+    /// Windows of the signals of `layout`
+    windows: Vec<RefCell<Window>>,
+    /// Whether a `NoteInput` reads the corresponding window, the callback skips the others
+    open: Arc<[AtomicBool]>,
 }
-impl NoteInput {
-    fn from_device(dev: cpal::Device) -> Result<Self> {
+impl DeviceStream {
+    fn new(dev: cpal::Device, window_len: usize, epoch: Instant) -> Result<Self> {
+        let name = dev.name()?;
         let cfg = dev.default_input_config()?;
-        let (samples_in, samples_out) = crossbeam_channel::bounded::<SampleBuf>(2);
-        for _ in 0..2 {
-            samples_in.send(SampleBuf::from_sample_format(cfg.sample_format()))?;
-        }
+        let layout = Layout::new(cfg.channels());
+        let rate = cfg.sample_rate().0;
+        let (outputs, windows): (Vec<_>, Vec<_>) = layout
+            .signals()
+            .map(|channel| {
+                let converter = Converter::new(layout.channels(), rate, channel);
+                let (producer, consumer) = ring::ring((RING_SECS * ANALYSIS_RATE) as usize);
+                (
+                    (converter, producer),
+                    RefCell::new(Window::new(consumer, window_len)),
+                )
+            })
+            .unzip();
+        let delay = outputs
+            .first()
+            .map_or(0., |(converter, _)| converter.delay());
+        let open: Arc<[AtomicBool]> = layout.signals().map(|_| AtomicBool::new(false)).collect();
         let stream_err = Arc::new(AtomicBool::new(false));
+        let clock = Arc::new(StreamClock::new());
         let stream = {
-            let samples_in = samples_in.clone();
-            let samples_out = samples_out.clone();
             let stream_err = stream_err.clone();
+            let open = open.clone();
+            let clock = clock.clone();
             let err_callback = move |err| {
-                error!("{}", err);
-                stream_err.swap(true, std::sync::atomic::Ordering::Release);
+                error!("Input device {}: {}", name, err);
+                stream_err.swap(true, Ordering::Release);
             };
             match cfg.sample_format() {
                 cpal::SampleFormat::I16 => dev.build_input_stream(
                     &cfg.into(),
-                    make_stream_callback::<i16>(samples_in, samples_out),
+                    make_stream_callback::<i16>(outputs, open, layout, rate, clock, epoch),
                     err_callback,
                 )?,
                 cpal::SampleFormat::U16 => dev.build_input_stream(
                     &cfg.into(),
-                    make_stream_callback::<u16>(samples_in, samples_out),
+                    make_stream_callback::<u16>(outputs, open, layout, rate, clock, epoch),
                     err_callback,
                 )?,
                 cpal::SampleFormat::F32 => dev.build_input_stream(
                     &cfg.into(),
-                    make_stream_callback::<f32>(samples_in, samples_out),
+                    make_stream_callback::<f32>(outputs, open, layout, rate, clock, epoch),
                     err_callback,
                 )?,
             }
@@ -186,88 +196,285 @@ impl NoteInput {
         Ok(Self {
             _dev: dev,
             _stream: stream,
-            samples_in,
-            samples_out,
+            layout,
             stream_err,
+            clock,
+            delay,
+            windows,
+            open,
         })
     }
-    fn from_id(dev_id: &DeviceId, host: &cpal::Host) -> Result<Self> {
-        let dev = host
-            .input_devices()?
-            .find_map(|dev| {
-                if dev.name().ok()? == dev_id.0 {
-                    Some(dev)
-                } else {
-                    None
-                }
-            })
-            .ok_or_else(|| anyhow!("Failed to lookup device"))?;
-        Self::from_device(dev)
+
+    /// Whether the stream failed, which doesn't affect the streams of other devices
+    fn failed(&self) -> bool {
+        self.stream_err.load(Ordering::Acquire)
+    }
+
+    /// Index of the window of `channel`, `None` for the mixed signal
+    fn output(&self, channel: Option<u16>) -> Result<usize> {
+        self.layout.output(channel).ok_or_else(|| {
+            anyhow!(
+                "Device has no channel {}, only {}",
+                channel.unwrap_or_default(),
+                self.layout.channels()
+            )
+        })
+    }
+}
+
+pub struct NoteInput {
+    stream: Rc<DeviceStream>,
+    output: usize,
+    detector: Detector,
+}
+impl NoteInput {
+    fn open(stream: Rc<DeviceStream>, channel: Option<u16>, detector: Detector) -> Result<Self> {
+        let output = stream.output(channel)?;
+        if stream.open[output].swap(true, Ordering::Relaxed) {
+            return Err(anyhow!("Input is already open"));
+        }
+        // Skip what was captured while the input was closed
+        let mut window = stream.windows[output].borrow_mut();
+        window.update();
+        window.clear();
+        drop(window);
+        Ok(Self {
+            stream,
+            output,
+            detector,
+        })
+    }
+
+    /// Number of captured samples dropped because they weren't read in time
+    #[must_use]
+    pub fn overflows(&self) -> usize {
+        self.stream.windows[self.output].borrow().overflows()
+    }
+}
+impl Drop for NoteInput {
+    fn drop(&mut self) {
+        self.stream.open[self.output].store(false, Ordering::Relaxed);
     }
 }
 impl super::NoteInput for NoteInput {
-    fn read_current(&self) -> Result<Option<Note>> {
-        if self.stream_err.load(std::sync::atomic::Ordering::Acquire) {
+    fn read_current(&self) -> Result<Option<Detection>> {
+        if self.stream.failed() {
             return Err(anyhow!("Stream encountered error"));
         }
-        let samples = match self.samples_out.try_recv() {
-            Ok(samples) => samples,
-            Err(_) => return Ok(None), //
-        };
-        info!("Read samples: {:?}", samples);
-        self.samples_in.send(samples)?;
-        Ok(None)
+        let mut window = self.stream.windows[self.output].borrow_mut();
+        let overflows = window.overflows();
+        if window.update() == 0 {
+            return Ok(None);
+        }
+        if window.overflows() > overflows {
+            warn!(
+                "Dropped {} captured samples",
+                window.overflows() - overflows
+            );
+        }
+        let time = self
+            .stream
+            .clock
+            .time(window.end(), ANALYSIS_RATE)
+            .unwrap_or_default()
+            - self.stream.delay;
+        let analysis = self.detector.analyze(window.samples());
+        Ok(Some(Detection::new(time, &analysis)))
     }
 }
 
-#[derive(Debug)]
+/// A device by its name
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct DeviceId(String);
 
+/// A device, or a single channel of it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InputId {
+    pub device: DeviceId,
+    /// `None` for all channels mixed together
+    #[serde(default)]
+    pub channel: Option<u16>,
+}
+impl std::fmt::Display for InputId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.channel {
+            Some(channel) => write!(f, "{} (channel {})", self.device.0, channel + 1),
+            None => write!(f, "{}", self.device.0),
+        }
+    }
+}
+
+/// The input devices with their channel counts as of the last enumeration
+#[derive(Clone, Default)]
+struct DeviceList(Arc<Mutex<Vec<(DeviceId, u16)>>>);
+impl DeviceList {
+    /// Enumerate the input devices of `host`, which may block for a while
+    fn refresh(&self, host: &cpal::Host) {
+        let devs = match host.input_devices() {
+            Ok(devs) => devs,
+            Err(err) => {
+                warn!("Failed to enumerate input devices: {}", err);
+                return;
+            }
+        };
+        let previous = self.get();
+        let devices = devs
+            .filter_map(|dev| {
+                let id = DeviceId(dev.name().ok()?);
+                // Devices may fail to report their configuration while in use, so their
+                // channels are kept
+                let channels = dev
+                    .default_input_config()
+                    .map(|cfg| cfg.channels())
+                    .ok()
+                    .or_else(|| {
+                        previous
+                            .iter()
+                            .find(|(known, _)| *known == id)
+                            .map(|(_, channels)| *channels)
+                    })
+                    .unwrap_or(1);
+                Some((id, channels))
+            })
+            .collect();
+        if let Ok(mut list) = self.0.lock() {
+            *list = devices;
+        }
+    }
+
+    fn get(&self) -> Vec<(DeviceId, u16)> {
+        self.0.lock().map(|list| list.clone()).unwrap_or_default()
+    }
+
+    /// Keep refreshing the list on a thread of its own, with a host of its own
+    #[cfg(not(target_arch = "wasm32"))]
+    fn watch(&self, host: cpal::HostId) -> Result<()> {
+        let list = self.clone();
+        std::thread::Builder::new()
+            .name("input devices".to_owned())
+            .spawn(move || {
+                let host = match cpal::host_from_id(host) {
+                    Ok(host) => host,
+                    Err(err) => {
+                        error!("Failed to watch input devices: {}", err);
+                        return;
+                    }
+                };
+                loop {
+                    std::thread::sleep(std::time::Duration::from_secs_f64(
+                        super::recovery::POLL_SECS,
+                    ));
+                    list.refresh(&host);
+                }
+            })?;
+        Ok(())
+    }
+}
+
 pub struct Platform {
     host: cpal::Host,
+    /// Enumerated in the background where possible, since enumerating may block rendering
+    devices: DeviceList,
+    /// Start of the timeline all inputs are aligned to
+    epoch: Instant,
+    /// Streams of devices with open inputs
+    streams: RefCell<HashMap<DeviceId, Weak<DeviceStream>>>,
 }
 impl Platform {
-    /// Create a `NoteInput` from the host's default input device
-    ///
-    /// # Errors
-    ///
-    /// If the platform's default input device cannot be opened
-    pub fn default_note_input(&self) -> Result<NoteInput> {
-        let dev = self
-            .host
-            .default_input_device()
-            .ok_or_else(|| anyhow!("No default input device"))?;
-        info!("Default device: {}", dev.name()?);
-        NoteInput::from_device(dev)
+    fn find_device(&self, id: &DeviceId) -> Result<cpal::Device> {
+        self.host
+            .input_devices()?
+            .find(|dev| dev.name().is_ok_and(|name| name == id.0))
+            .ok_or_else(|| anyhow!("Failed to lookup device {}", id.0))
+    }
+
+    /// Open `channel` of `dev`, sharing the device's stream with its other open channels
+    fn open_input(&self, dev: cpal::Device, channel: Option<u16>) -> Result<NoteInput> {
+        let detector = Detector::new(f64::from(ANALYSIS_RATE));
+        let id = DeviceId(dev.name()?);
+        let mut streams = self.streams.borrow_mut();
+        let stream = match streams.get(&id).and_then(Weak::upgrade) {
+            Some(stream) if !stream.failed() => stream,
+            _ => {
+                let stream = Rc::new(DeviceStream::new(dev, detector.window_len(), self.epoch)?);
+                streams.insert(id, Rc::downgrade(&stream));
+                stream
+            }
+        };
+        NoteInput::open(stream, channel, detector)
     }
 }
 
 impl super::PlatformApi for Platform {
     type InitSettings = InitSettings;
 
-    type NoteInputId = DeviceId;
+    type NoteInputId = InputId;
 
     type NoteInput = NoteInput;
 
     fn init(settings: &Self::InitSettings) -> Result<Self> {
         info!("Initializing audio backend {}", settings.host.0.name());
+        let host = cpal::host_from_id(settings.host.0)?;
+        let devices = DeviceList::default();
+        devices.refresh(&host);
+        #[cfg(not(target_arch = "wasm32"))]
+        devices.watch(settings.host.0)?;
         Ok(Self {
-            host: cpal::host_from_id(settings.host.0)?,
+            host,
+            devices,
+            epoch: Instant::now(),
+            streams: RefCell::default(),
+        })
+    }
+
+    fn default_note_input_id(&self) -> Option<Self::NoteInputId> {
+        Some(InputId {
+            device: DeviceId(self.host.default_input_device()?.name().ok()?),
+            channel: None,
         })
     }
 
     fn list_note_inputs(&self) -> Vec<Self::NoteInputId> {
-        let inputs = match self.host.input_devices() {
-            Ok(devs) => devs,
-            Err(_) => return vec![],
-        };
-        inputs
-            .filter_map(|input| -> Option<Self::NoteInputId> { Some(DeviceId(input.name().ok()?)) })
-            .collect::<Vec<_>>()
+        self.devices
+            .get()
+            .into_iter()
+            .flat_map(|(device, channels)| {
+                Layout::new(channels).signals().map(move |channel| InputId {
+                    device: device.clone(),
+                    channel,
+                })
+            })
+            .collect()
+    }
+
+    fn now(&self) -> f64 {
+        self.epoch.elapsed().as_secs_f64()
     }
 
     fn create_note_input(&self, id: &Self::NoteInputId) -> Result<Self::NoteInput> {
-        NoteInput::from_id(id, &self.host)
+        self.open_input(self.find_device(&id.device)?, id.channel)
+    }
+}
+
+impl super::recovery::Backend for Platform {
+    type Id = InputId;
+    type Device = DeviceId;
+    type Input = NoteInput;
+
+    fn devices(&self) -> Vec<Self::Device> {
+        // Without threads, devices are enumerated when asked for
+        #[cfg(target_arch = "wasm32")]
+        self.devices.refresh(&self.host);
+        self.devices.get().into_iter().map(|(id, _)| id).collect()
+    }
+
+    fn device(id: &Self::Id) -> Self::Device {
+        id.device.clone()
+    }
+
+    fn open(&self, id: &Self::Id) -> Result<Self::Input> {
+        super::PlatformApi::create_note_input(self, id)
     }
 }
 
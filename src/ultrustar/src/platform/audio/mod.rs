@@ -1,24 +1,78 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 
 use anyhow::Result;
 
+pub mod clock;
 mod cpal;
-use crate::SettingsTrait;
+pub mod pipeline;
+pub mod pitch;
+pub mod players;
+pub mod recovery;
+pub mod ring;
+use crate::{model::scoring::PitchSample, SettingsTrait};
+use serde::{de::DeserializeOwned, Serialize};
 
 pub use tune::note::Note;
 
+/// A note detected in captured audio
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Detection {
+    /// Capture time of the end of the analyzed samples on the timeline of `PlatformApi::now`
+    pub time: f64,
+    /// Nearest note to the detected pitch, `None` if the signal isn't periodic
+    pub note: Option<Note>,
+    /// Deviation of the detected pitch from `note`, between -50 and 50
+    pub cents: f64,
+    /// How periodic the signal is, between 0 and 1
+    pub confidence: f32,
+    /// RMS level between 0 and 1
+    pub rms: f32,
+}
+impl Detection {
+    #[must_use]
+    pub fn new(time: f64, analysis: &pitch::Analysis) -> Self {
+        let pitch = analysis.frequency.map(pitch::midi_pitch);
+        #[allow(clippy::cast_possible_truncation)]
+        let note = pitch.map(|pitch| Note::from_midi_number(pitch.round() as i32));
+        Self {
+            time,
+            note,
+            cents: pitch.map_or(0., |pitch| (pitch - pitch.round()) * 100.),
+            confidence: analysis.confidence,
+            rms: analysis.rms,
+        }
+    }
+    /// The detected pitch as fractional MIDI note number
+    #[must_use]
+    pub fn pitch(&self) -> Option<f64> {
+        self.note
+            .map(|note| f64::from(note.midi_number()) + self.cents / 100.)
+    }
+    /// The detection as input for scoring
+    #[must_use]
+    pub fn pitch_sample(&self) -> PitchSample {
+        PitchSample {
+            time: self.time,
+            pitch: self.pitch(),
+            volume: self.rms,
+        }
+    }
+}
+
 pub trait NoteInput {
-    /// Extract the most recent note sample
+    /// Detect the note in the samples captured since the last call.
+    ///
+    /// Returns `None` if nothing has been captured since.
     ///
     /// # Errors
     ///
     /// If there is an error with the `NoteInput` device
-    fn read_current(&self) -> Result<Option<Note>>;
+    fn read_current(&self) -> Result<Option<Detection>>;
 }
 
 pub trait PlatformApi: Sized {
     type InitSettings: SettingsTrait;
-    type NoteInputId: Debug;
+    type NoteInputId: Clone + Debug + Display + PartialEq + Serialize + DeserializeOwned;
     type NoteInput: NoteInput;
 
     /// Initialize the platform audio api
@@ -28,9 +82,23 @@ pub trait PlatformApi: Sized {
     /// Initialization may fail
     fn init(settings: &Self::InitSettings) -> Result<Self>;
 
-    /// List available `NoteInput`s by their identifiers
+    /// Seconds since the platform audio api was initialized.
+    ///
+    /// Detections of all `NoteInput`s are timed on this timeline, so inputs of different devices
+    /// may be scored together.
+    fn now(&self) -> f64;
+
+    /// The input of the host's default device, `None` if there is no input device
+    fn default_note_input_id(&self) -> Option<Self::NoteInputId>;
+
+    /// List available `NoteInput`s by their identifiers.
+    ///
+    /// Each channel of a multi-channel device is a separate input, e.g. for one microphone per
+    /// channel, in addition to all channels mixed together. Doesn't block, since it is called
+    /// while rendering.
     fn list_note_inputs(&self) -> Vec<Self::NoteInputId>;
-    /// Create a new `NodeInput` device
+    /// Create a new `NodeInput` device. Several inputs may be open at once, and errors of one
+    /// device are only reported by its own inputs.
     ///
     /// # Errors
     ///
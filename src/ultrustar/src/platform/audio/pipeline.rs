@@ -0,0 +1,365 @@
+//! Conversion of captured samples into a device-independent format
+//!
+//! Whatever the sample format, channel count and rate of a device, captured frames are
+//! normalized to `f32`, either mixed down to mono or split into channels, and resampled to
+//! `ANALYSIS_RATE`, so analysis behaves the same on all devices. Each signal is converted on its
+//! own, so signals nobody listens to can be skipped.
+
+/// Sample rate of the converted signals, enough for the fundamentals and first overtones of
+/// singing voices
+pub const ANALYSIS_RATE: u32 = 16_000;
+
+/// A sample in one of the formats devices deliver
+pub trait CaptureSample: Copy {
+    /// The sample scaled to `-1.0..=1.0`
+    fn to_f32(self) -> f32;
+}
+impl CaptureSample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+impl CaptureSample for i16 {
+    fn to_f32(self) -> f32 {
+        f32::from(self) / -f32::from(i16::MIN)
+    }
+}
+impl CaptureSample for u16 {
+    fn to_f32(self) -> f32 {
+        (f32::from(self) - 32768.) / 32768.
+    }
+}
+
+/// A second-order section of an IIR filter, in transposed direct form II
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b: [f32; 3],
+    /// Feedback coefficients, normalized so that `a0` is 1
+    a: [f32; 2],
+    state: [f32; 2],
+}
+impl Biquad {
+    /// A low-pass section with `cutoff` in cycles per sample and quality factor `q`
+    fn low_pass(cutoff: f64, q: f64) -> Self {
+        let omega = std::f64::consts::TAU * cutoff;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2. * q);
+        let a0 = 1. + alpha;
+        let b0 = (1. - cos) / 2. / a0;
+        #[allow(clippy::cast_possible_truncation)]
+        Self {
+            b: [b0 as f32, (2. * b0) as f32, b0 as f32],
+            a: [(-2. * cos / a0) as f32, ((1. - alpha) / a0) as f32],
+            state: [0.; 2],
+        }
+    }
+
+    /// Group delay at low frequencies, in samples
+    fn delay(&self) -> f64 {
+        let [b0, b1, b2] = self.b.map(f64::from);
+        let [a1, a2] = self.a.map(f64::from);
+        (b1 + 2. * b2) / (b0 + b1 + b2) - (a1 + 2. * a2) / (1. + a1 + a2)
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let out = self.b[0] * sample + self.state[0];
+        self.state[0] = self.b[1] * sample - self.a[0] * out + self.state[1];
+        self.state[1] = self.b[2] * sample - self.a[1] * out;
+        out
+    }
+}
+
+/// Resamples a signal by linear interpolation.
+///
+/// When downsampling, an 8th-order Butterworth low-pass filter with its cutoff at 80% of the
+/// output's Nyquist frequency attenuates frequencies which would alias, by 48 dB per octave
+/// above the cutoff.
+#[derive(Clone, Debug)]
+pub struct Resampler {
+    /// Input samples per output sample
+    step: f64,
+    /// Position of the next output sample after `previous`, in input samples
+    position: f64,
+    previous: f32,
+    /// Anti-aliasing filter, empty unless downsampling
+    filter: Vec<Biquad>,
+    input_rate: u32,
+}
+impl Resampler {
+    /// Order of the anti-aliasing filter
+    const ORDER: u32 = 8;
+
+    #[must_use]
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        let step = f64::from(input_rate) / f64::from(output_rate);
+        let filter = if step > 1. {
+            let cutoff = 0.4 / step;
+            // Butterworth poles, split into conjugate pairs
+            (0..Self::ORDER / 2)
+                .map(|pair| {
+                    let angle =
+                        std::f64::consts::PI * f64::from(2 * pair + 1) / f64::from(2 * Self::ORDER);
+                    Biquad::low_pass(cutoff, 1. / (2. * angle.cos()))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self {
+            step,
+            position: 1.,
+            previous: 0.,
+            filter,
+            input_rate,
+        }
+    }
+
+    /// How much the output lags behind the input at the frequencies of singing voices, in
+    /// seconds
+    #[must_use]
+    pub fn delay(&self) -> f64 {
+        self.filter.iter().map(Biquad::delay).sum::<f64>() / f64::from(self.input_rate)
+    }
+
+    /// Feed the next input sample, passing the resulting output samples to `emit`
+    pub fn push(&mut self, sample: f32, mut emit: impl FnMut(f32)) {
+        let current = self
+            .filter
+            .iter_mut()
+            .fold(sample, |sample, section| section.process(sample));
+        while self.position <= 1. {
+            #[allow(clippy::cast_possible_truncation)]
+            let fraction = self.position as f32;
+            emit(self.previous + (current - self.previous) * fraction);
+            self.position += self.step;
+        }
+        self.position -= 1.;
+        self.previous = current;
+    }
+
+    /// Skip `count` input samples. Returns the number of output samples they would have
+    /// resulted in.
+    pub fn skip(&mut self, count: usize) -> usize {
+        let mut emitted = 0;
+        for _ in 0..count {
+            while self.position <= 1. {
+                emitted += 1;
+                self.position += self.step;
+            }
+            self.position -= 1.;
+        }
+        emitted
+    }
+}
+
+/// The signals a device's frames are converted to: all channels mixed together, followed by
+/// each channel on its own if there are several
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    channels: u16,
+}
+impl Layout {
+    #[must_use]
+    pub fn new(channels: u16) -> Self {
+        Self {
+            channels: channels.max(1),
+        }
+    }
+
+    #[must_use]
+    pub fn channels(self) -> u16 {
+        self.channels
+    }
+
+    /// Number of signals
+    #[must_use]
+    pub fn outputs(self) -> usize {
+        self.signals().count()
+    }
+
+    /// The channel of each signal, `None` for the mix
+    pub fn signals(self) -> impl Iterator<Item = Option<u16>> {
+        let split = (self.channels > 1).then_some(0..self.channels);
+        std::iter::once(None).chain(split.into_iter().flatten().map(Some))
+    }
+
+    /// Index of the signal of `channel`, `None` if the device has no such channel. Mono devices
+    /// only have the mix.
+    #[must_use]
+    pub fn output(self, channel: Option<u16>) -> Option<usize> {
+        match channel {
+            Some(channel) if channel >= self.channels => None,
+            Some(_) if self.channels == 1 => Some(0),
+            channel => self.signals().position(|signal| signal == channel),
+        }
+    }
+}
+
+/// Converts interleaved frames of a device into a normalized signal at `ANALYSIS_RATE`
+#[derive(Clone, Debug)]
+pub struct Converter {
+    channels: usize,
+    /// The channel to extract, `None` to mix all channels
+    channel: Option<usize>,
+    resampler: Resampler,
+}
+impl Converter {
+    /// Convert frames of `channels` channels at `device_rate`, either extracting `channel` or
+    /// mixing all channels if it's `None`
+    #[must_use]
+    pub fn new(channels: u16, device_rate: u32, channel: Option<u16>) -> Self {
+        Self {
+            channels: usize::from(channels.max(1)),
+            channel: channel.map(usize::from),
+            resampler: Resampler::new(device_rate, ANALYSIS_RATE),
+        }
+    }
+
+    /// How much the converted signal lags behind the captured frames, in seconds
+    #[must_use]
+    pub fn delay(&self) -> f64 {
+        self.resampler.delay()
+    }
+
+    /// Convert interleaved frames, passing each converted sample to `emit`. Doesn't allocate,
+    /// so it may run in audio callbacks.
+    pub fn convert<T: CaptureSample>(&mut self, interleaved: &[T], mut emit: impl FnMut(f32)) {
+        #[allow(clippy::cast_precision_loss)]
+        let scale = 1. / self.channels as f32;
+        for frame in interleaved.chunks_exact(self.channels) {
+            let sample = match self.channel {
+                Some(channel) => frame[channel].to_f32(),
+                None => frame.iter().map(|sample| sample.to_f32()).sum::<f32>() * scale,
+            };
+            self.resampler.push(sample, &mut emit);
+        }
+    }
+
+    /// Skip interleaved frames nobody listens to. Returns the number of samples which
+    /// converting them would have emitted, to keep counting time.
+    pub fn skip(&mut self, interleaved: usize) -> usize {
+        self.resampler.skip(interleaved / self.channels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CaptureSample, Converter, Layout, Resampler, ANALYSIS_RATE};
+    use crate::platform::audio::pitch::{midi_pitch, Detector};
+
+    #[test]
+    fn normalize() {
+        assert!((i16::MIN.to_f32() + 1.).abs() < f32::EPSILON);
+        assert!(0_i16.to_f32().abs() < f32::EPSILON);
+        assert!((u16::MAX.to_f32() - 1.).abs() < 1e-4);
+        assert!((0_u16.to_f32() + 1.).abs() < f32::EPSILON);
+        assert!(32768_u16.to_f32().abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn resample() {
+        for (input, output) in [
+            (48000, 16000),
+            (44100, 16000),
+            (8000, 16000),
+            (16000, 16000),
+        ] {
+            let mut resampler = Resampler::new(input, output);
+            let mut count = 0;
+            for _ in 0..input {
+                resampler.push(0.5, |_| count += 1);
+            }
+            assert!(
+                (count - i64::from(output)).abs() <= 1,
+                "{} -> {}",
+                input,
+                count
+            );
+        }
+        // Ramps stay ramps when upsampling
+        let mut resampler = Resampler::new(1, 2);
+        let mut out = Vec::new();
+        for sample in [0., 1., 2.] {
+            resampler.push(sample, |sample| out.push(sample));
+        }
+        assert_eq!(out, vec![0., 0.5, 1., 1.5, 2.]);
+        assert!(resampler.delay().abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn anti_aliasing() {
+        // Amplitude after resampling a tone from 48 kHz to 16 kHz, once the filter settled
+        let amplitude = |frequency: f64| {
+            let mut resampler = Resampler::new(48000, 16000);
+            let mut out = Vec::new();
+            for idx in 0..48000 {
+                let phase = std::f64::consts::TAU * frequency * f64::from(idx) / 48000.;
+                #[allow(clippy::cast_possible_truncation)]
+                resampler.push(phase.sin() as f32, |sample| out.push(sample));
+            }
+            out[8000..].iter().copied().fold(0., f32::max)
+        };
+        // 10 kHz would alias to 6 kHz, 13 kHz to 3 kHz in the range of voices
+        assert!(amplitude(10000.) < 0.02);
+        assert!(amplitude(13000.) < 0.001);
+        assert!((amplitude(1000.) - 1.).abs() < 0.01);
+        // The delay is a few input samples
+        let delay = Resampler::new(48000, 16000).delay() * 48000.;
+        assert!(delay > 1. && delay < 20., "{}", delay);
+    }
+
+    #[test]
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    fn convert() {
+        // A stereo device at 44.1 kHz with a tone on the left and silence on the right
+        let frames = 44100 / 5;
+        let interleaved: Vec<i16> = (0..frames)
+            .flat_map(|idx| {
+                let phase = std::f64::consts::TAU * 330. * f64::from(idx) / 44100.;
+                [(phase.sin() * 16000.) as i16, 0]
+            })
+            .collect();
+        let mut signals = [Vec::new(), Vec::new()];
+        for (channel, signal) in signals.iter_mut().enumerate() {
+            #[allow(clippy::cast_possible_truncation)]
+            let mut converter = Converter::new(2, 44100, Some(channel as u16));
+            converter.convert(&interleaved, |sample| signal.push(sample));
+        }
+        assert!((signals[0].len() as f64 - f64::from(ANALYSIS_RATE) / 5.).abs() <= 1.);
+        assert!(signals[1].iter().all(|sample| sample.abs() < f32::EPSILON));
+
+        let detector = Detector::new(f64::from(ANALYSIS_RATE));
+        let window = &signals[0][signals[0].len() - detector.window_len()..];
+        let frequency = detector.analyze(window).frequency.unwrap();
+        assert!((midi_pitch(frequency) - midi_pitch(330.)).abs() < 0.05);
+
+        let mut mono = Converter::new(2, 44100, None);
+        let mut mixed = Vec::new();
+        mono.convert(&interleaved, |sample| mixed.push(sample));
+        assert_eq!(mixed.len(), signals[0].len());
+        let max = mixed.iter().copied().fold(0., f32::max);
+        assert!((max - 0.25).abs() < 0.02);
+        // Skipping counts the same samples
+        let mut skipping = Converter::new(2, 44100, None);
+        assert_eq!(skipping.skip(interleaved.len()), mixed.len());
+    }
+
+    #[test]
+    fn layout() {
+        let stereo = Layout::new(2);
+        assert_eq!(stereo.outputs(), 3);
+        assert_eq!(
+            stereo.signals().collect::<Vec<_>>(),
+            vec![None, Some(0), Some(1)]
+        );
+        assert_eq!(stereo.output(None), Some(0));
+        assert_eq!(stereo.output(Some(1)), Some(2));
+        assert_eq!(stereo.output(Some(2)), None);
+        let mono = Layout::new(1);
+        assert_eq!(mono.signals().collect::<Vec<_>>(), vec![None]);
+        assert_eq!(mono.output(Some(0)), Some(0));
+        assert_eq!(mono.output(Some(1)), None);
+        // Devices reporting no channels still have the mix
+        assert_eq!(Layout::new(0).outputs(), 1);
+    }
+}
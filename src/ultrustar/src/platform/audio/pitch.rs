@@ -0,0 +1,171 @@
+//! Monophonic pitch detection with the YIN algorithm
+//!
+//! See de Cheveigné and Kawahara, "YIN, a fundamental frequency estimator for speech and music".
+//! The period is the first dip of the cumulative mean normalized difference function below a
+//! threshold, refined by parabolic interpolation.
+
+/// Lowest detected frequency, a little below the lowest notes of bass singers
+pub const MIN_HZ: f64 = 60.;
+/// Highest detected frequency, a little above the highest notes of sopranos
+pub const MAX_HZ: f64 = 1500.;
+/// Dips of the normalized difference below this indicate a period
+const THRESHOLD: f32 = 0.15;
+/// RMS below which the signal is considered silent
+const SILENCE_RMS: f32 = 1e-3;
+
+/// Result of analyzing a window of samples
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Analysis {
+    /// Fundamental frequency in Hz, `None` if the signal isn't periodic
+    pub frequency: Option<f64>,
+    /// How periodic the signal is, between 0 and 1
+    pub confidence: f32,
+    /// RMS of the samples, between 0 and 1 for normalized samples
+    pub rms: f32,
+}
+
+/// Convert a frequency into a fractional MIDI note number
+#[must_use]
+pub fn midi_pitch(frequency: f64) -> f64 {
+    69. + 12. * (frequency / 440.).log2()
+}
+
+/// Detects the pitch of windows of samples taken at a fixed rate
+#[derive(Clone, Debug)]
+pub struct Detector {
+    sample_rate: f64,
+}
+impl Detector {
+    #[must_use]
+    pub fn new(sample_rate: f64) -> Self {
+        Self { sample_rate }
+    }
+
+    /// Number of samples needed to detect the lowest frequencies
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn window_len(&self) -> usize {
+        2 * (self.sample_rate / MIN_HZ).ceil() as usize
+    }
+
+    /// Analyze mono samples normalized to `-1.0..=1.0`. Windows shorter than `window_len` miss
+    /// the lowest frequencies.
+    #[must_use]
+    pub fn analyze(&self, window: &[f32]) -> Analysis {
+        #[allow(clippy::cast_precision_loss)]
+        let rms = (window.iter().map(|x| x * x).sum::<f32>() / window.len().max(1) as f32).sqrt();
+        let silent = Analysis {
+            frequency: None,
+            confidence: 0.,
+            rms,
+        };
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let min_period = ((self.sample_rate / MAX_HZ).floor() as usize).max(2);
+        let max_period = window.len() / 2;
+        if rms < SILENCE_RMS || max_period <= min_period + 1 {
+            return silent;
+        }
+        let diff = normalized_difference(window, max_period);
+        let period = (min_period..max_period)
+            .find(|&tau| diff[tau] < THRESHOLD)
+            .map(|tau| {
+                // Descend to the bottom of the dip
+                (tau..max_period - 1)
+                    .find(|&tau| diff[tau + 1] >= diff[tau])
+                    .unwrap_or(max_period - 1)
+            });
+        let Some(tau) = period else {
+            let best = diff[min_period..max_period]
+                .iter()
+                .copied()
+                .fold(1., f32::min);
+            return Analysis {
+                confidence: (1. - best).clamp(0., 1.),
+                ..silent
+            };
+        };
+        let (before, at, after) = (diff[tau - 1], diff[tau], diff[tau + 1]);
+        let curvature = before - 2. * at + after;
+        let shift = if curvature.abs() > f32::EPSILON {
+            (0.5 * (before - after) / curvature).clamp(-1., 1.)
+        } else {
+            0.
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let period = tau as f64 + f64::from(shift);
+        Analysis {
+            frequency: Some(self.sample_rate / period),
+            confidence: (1. - at).clamp(0., 1.),
+            rms,
+        }
+    }
+}
+
+/// The cumulative mean normalized difference function for lags `0..=max_period`
+fn normalized_difference(window: &[f32], max_period: usize) -> Vec<f32> {
+    let len = window.len() - max_period;
+    let mut diff = vec![0.; max_period + 1];
+    for (tau, d) in diff.iter_mut().enumerate().skip(1) {
+        *d = window[..len]
+            .iter()
+            .zip(&window[tau..tau + len])
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum();
+    }
+    diff[0] = 1.;
+    let mut sum = 0.;
+    for (tau, d) in diff.iter_mut().enumerate().skip(1) {
+        sum += *d;
+        #[allow(clippy::cast_precision_loss)]
+        let normalized = if sum > 0. { *d * tau as f32 / sum } else { 1. };
+        *d = normalized;
+    }
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use super::{midi_pitch, Detector};
+
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    fn sine(frequency: f64, sample_rate: f64, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|idx| {
+                let phase = std::f64::consts::TAU * frequency * idx as f64 / sample_rate;
+                (0.5 * phase.sin() + 0.2 * (2. * phase).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detect() {
+        let detector = Detector::new(48000.);
+        for frequency in [82.4, 220., 261.63, 440., 987.77] {
+            let window = sine(frequency, 48000., detector.window_len());
+            let analysis = detector.analyze(&window);
+            let detected = analysis.frequency.unwrap();
+            let cents = (midi_pitch(detected) - midi_pitch(frequency)) * 100.;
+            assert!(
+                cents.abs() < 5.,
+                "{} Hz detected as {} Hz",
+                frequency,
+                detected
+            );
+            assert!(analysis.confidence > 0.9);
+            assert!(analysis.rms > 0.3);
+        }
+        let silence = detector.analyze(&vec![0.; 2000]);
+        assert_eq!(silence.frequency, None);
+        // Pseudo-random noise isn't periodic
+        let noise: Vec<f32> = (0..2000u32)
+            .map(|idx| {
+                let hash = idx.wrapping_mul(2_654_435_761).rotate_left(13) ^ 0x5bd1_e995;
+                #[allow(clippy::cast_precision_loss)]
+                let value = (hash % 2000) as f32 / 1000. - 1.;
+                value
+            })
+            .collect();
+        assert_eq!(detector.analyze(&noise).frequency, None);
+        assert!((midi_pitch(440.) - 69.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,182 @@
+//! Assignment of note inputs to player slots
+//!
+//! The input of each slot is saved by its identifier, which names the device, so it's found
+//! again when devices are enumerated in another order. Slots whose device is missing at startup
+//! fall back to the default input, unless another slot already uses it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// The input chosen for each player slot, `None` if a slot has none
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PlayerInputs<Id>(Vec<Option<Id>>);
+impl<Id> Default for PlayerInputs<Id> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+/// The input a player slot uses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Resolved<Id> {
+    Unassigned,
+    /// The saved input is available
+    Saved(Id),
+    /// The saved input is missing, so the default input is used instead
+    Fallback {
+        missing: Id,
+        default: Id,
+    },
+    /// Neither the saved input nor the default input are available
+    Missing(Id),
+}
+impl<Id> Resolved<Id> {
+    /// The input to open
+    #[must_use]
+    pub fn id(&self) -> Option<&Id> {
+        match self {
+            Self::Saved(id) | Self::Fallback { default: id, .. } => Some(id),
+            Self::Unassigned | Self::Missing(_) => None,
+        }
+    }
+}
+impl<Id: Display> Resolved<Id> {
+    /// A warning for the user if the saved input is missing
+    #[must_use]
+    pub fn warning(&self) -> Option<String> {
+        match self {
+            Self::Fallback { missing, default } => Some(format!(
+                "Input {} is missing, using {} instead",
+                missing, default
+            )),
+            Self::Missing(missing) => Some(format!("Input {} is missing", missing)),
+            Self::Unassigned | Self::Saved(_) => None,
+        }
+    }
+}
+
+impl<Id: Clone + PartialEq> PlayerInputs<Id> {
+    /// Whether no slot has an input
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(Option::is_none)
+    }
+
+    /// Number of slots up to the last one with an input
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The saved input of `slot`
+    #[must_use]
+    pub fn get(&self, slot: usize) -> Option<&Id> {
+        self.0.get(slot)?.as_ref()
+    }
+
+    /// Assign `input` to `slot`, removing it from any other slot since inputs can't be shared
+    pub fn set(&mut self, slot: usize, input: Option<Id>) {
+        if input.is_some() {
+            for other in &mut self.0 {
+                if *other == input {
+                    *other = None;
+                }
+            }
+        }
+        if self.0.len() <= slot {
+            self.0.resize(slot + 1, None);
+        }
+        self.0[slot] = input;
+        while self.0.last() == Some(&None) {
+            self.0.pop();
+        }
+    }
+
+    /// Match the saved inputs against the `available` ones, with `default` as the fallback for
+    /// a single missing input
+    #[must_use]
+    pub fn resolve(&self, available: &[Id], default: Option<&Id>) -> Vec<Resolved<Id>> {
+        let mut resolved: Vec<_> = self
+            .0
+            .iter()
+            .map(|saved| match saved {
+                None => Resolved::Unassigned,
+                Some(id) if available.contains(id) => Resolved::Saved(id.clone()),
+                Some(id) => Resolved::Missing(id.clone()),
+            })
+            .collect();
+        let mut default = default.filter(|default| {
+            !resolved
+                .iter()
+                .any(|slot| matches!(slot, Resolved::Saved(id) if id == *default))
+        });
+        for slot in &mut resolved {
+            if let Resolved::Missing(missing) = slot {
+                if let Some(default) = default.take() {
+                    *slot = Resolved::Fallback {
+                        missing: missing.clone(),
+                        default: default.clone(),
+                    };
+                }
+            }
+        }
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PlayerInputs, Resolved};
+
+    #[test]
+    fn resolve() {
+        let mut inputs = PlayerInputs::default();
+        assert!(inputs.is_empty());
+        inputs.set(0, Some("headset"));
+        inputs.set(2, Some("dongle 1"));
+        inputs.set(3, Some("dongle 2"));
+        assert_eq!(inputs.get(1), None);
+        assert_eq!(inputs.len(), 4);
+        let json = serde_json::to_string(&inputs).unwrap();
+        assert_eq!(json, r#"["headset",null,"dongle 1","dongle 2"]"#);
+        assert_eq!(
+            serde_json::from_str::<PlayerInputs<_>>(&json).unwrap(),
+            inputs
+        );
+
+        let resolved = inputs.resolve(&["default", "headset"], Some(&"default"));
+        assert_eq!(
+            resolved,
+            vec![
+                Resolved::Saved("headset"),
+                Resolved::Unassigned,
+                Resolved::Fallback {
+                    missing: "dongle 1",
+                    default: "default"
+                },
+                Resolved::Missing("dongle 2"),
+            ]
+        );
+        assert_eq!(resolved[2].id(), Some(&"default"));
+        assert_eq!(resolved[3].id(), None);
+        assert_eq!(
+            resolved[2].warning().unwrap(),
+            "Input dongle 1 is missing, using default instead"
+        );
+        assert_eq!(resolved[0].warning(), None);
+        // The default input is taken
+        let resolved = inputs.resolve(&["headset"], Some(&"headset"));
+        assert_eq!(resolved[2], Resolved::Missing("dongle 1"));
+
+        // Inputs move between slots
+        inputs.set(1, Some("headset"));
+        inputs.set(3, None);
+        assert_eq!(inputs.get(0), None);
+        assert_eq!(inputs.get(1), Some(&"headset"));
+        assert_eq!(
+            serde_json::to_string(&inputs).unwrap(),
+            r#"[null,"headset","dongle 1"]"#
+        );
+    }
+}
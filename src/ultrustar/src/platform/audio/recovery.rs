@@ -0,0 +1,551 @@
+//! Recovery of note inputs whose devices fail or are unplugged
+//!
+//! A `Supervisor` owns the inputs of all player slots. Inputs that fail are closed, and the
+//! available devices are enumerated periodically to notice devices coming and going. An input is
+//! available as long as its device is, since how a device reports its channels may change. A
+//! slot whose own input is missing switches to the fallback input, and back once its own input
+//! returns. Everything that happens is reported as an `Event`, e.g. for the UI.
+
+use super::players::Resolved;
+use anyhow::Result;
+
+/// Seconds between enumerations of the available devices
+pub const POLL_SECS: f64 = 1.;
+
+/// The inputs of an audio backend
+pub trait Backend {
+    type Id: Clone + PartialEq;
+    type Device: Clone + PartialEq;
+    type Input;
+
+    /// The currently available devices. Called while rendering, so it must not block.
+    fn devices(&self) -> Vec<Self::Device>;
+    /// The device of an input
+    fn device(id: &Self::Id) -> Self::Device;
+    /// Open an input
+    ///
+    /// # Errors
+    ///
+    /// If the input cannot be opened
+    fn open(&self, id: &Self::Id) -> Result<Self::Input>;
+}
+
+/// A change of the available devices or of the input of a slot
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event<Id, Device> {
+    /// A device became available
+    Connected(Device),
+    /// A device is no longer available
+    Disconnected(Device),
+    /// The input of a slot failed or disappeared and was closed
+    Lost {
+        slot: usize,
+        input: Id,
+        reason: String,
+    },
+    /// A slot opened an input, which is the fallback if its own input is missing
+    Opened {
+        slot: usize,
+        input: Id,
+        fallback: bool,
+    },
+    /// A slot failed to open an input
+    OpenFailed {
+        slot: usize,
+        input: Id,
+        reason: String,
+    },
+}
+
+struct Slot<B: Backend> {
+    /// The input chosen for the slot
+    own: B::Id,
+    active: Option<(B::Id, B::Input)>,
+    /// Input which failed to open last time, so failures are reported once
+    failing: Option<B::Id>,
+}
+impl<B: Backend> Slot<B> {
+    fn is_using(&self, id: &B::Id) -> bool {
+        matches!(&self.active, Some((active, _)) if active == id)
+    }
+
+    /// Open `id`, replacing the active input if successful
+    fn open(
+        &mut self,
+        backend: &B,
+        idx: usize,
+        id: &B::Id,
+        events: &mut Vec<Event<B::Id, B::Device>>,
+    ) -> bool {
+        match backend.open(id) {
+            Ok(input) => {
+                self.active = Some((id.clone(), input));
+                self.failing = None;
+                events.push(Event::Opened {
+                    slot: idx,
+                    input: id.clone(),
+                    fallback: *id != self.own,
+                });
+                true
+            }
+            Err(err) => {
+                if self.failing.as_ref() != Some(id) {
+                    self.failing = Some(id.clone());
+                    events.push(Event::OpenFailed {
+                        slot: idx,
+                        input: id.clone(),
+                        reason: err.to_string(),
+                    });
+                }
+                false
+            }
+        }
+    }
+
+    fn close(&mut self, idx: usize, reason: String, events: &mut Vec<Event<B::Id, B::Device>>) {
+        if let Some((input, _)) = self.active.take() {
+            events.push(Event::Lost {
+                slot: idx,
+                input,
+                reason,
+            });
+        }
+    }
+}
+
+/// Keeps the inputs of all player slots open
+pub struct Supervisor<B: Backend> {
+    /// `None` for slots without input
+    slots: Vec<Option<Slot<B>>>,
+    fallback: Option<B::Id>,
+    available: Vec<B::Device>,
+    next_poll: f64,
+    events: Vec<Event<B::Id, B::Device>>,
+}
+impl<B: Backend> Supervisor<B> {
+    /// Open the inputs the slots `resolved` to, with `fallback` for slots whose input goes
+    /// missing later on
+    pub fn new(backend: &B, resolved: &[Resolved<B::Id>], fallback: Option<B::Id>) -> Self {
+        let mut events = Vec::new();
+        let slots = resolved
+            .iter()
+            .enumerate()
+            .map(|(idx, resolved)| {
+                let own = match resolved {
+                    Resolved::Unassigned => return None,
+                    Resolved::Saved(id)
+                    | Resolved::Missing(id)
+                    | Resolved::Fallback { missing: id, .. } => id.clone(),
+                };
+                let mut slot = Slot {
+                    own,
+                    active: None,
+                    failing: None,
+                };
+                if let Some(id) = resolved.id() {
+                    slot.open(backend, idx, id, &mut events);
+                }
+                Some(slot)
+            })
+            .collect();
+        Self {
+            slots,
+            fallback,
+            available: backend.devices(),
+            next_poll: 0.,
+            events,
+        }
+    }
+
+    /// Number of slots
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Give `slot` its own input `id`, or none, taking it away from any other slot. The input
+    /// is opened at the next poll.
+    pub fn assign(&mut self, slot: usize, id: Option<B::Id>) {
+        if self.slots.len() <= slot {
+            self.slots.resize_with(slot + 1, || None);
+        }
+        for (idx, other) in self.slots.iter_mut().enumerate() {
+            let reassigned = other
+                .as_ref()
+                .is_some_and(|other| idx == slot || Some(&other.own) == id.as_ref());
+            if reassigned {
+                if let Some(mut other) = other.take() {
+                    other.close(idx, "Reassigned".to_owned(), &mut self.events);
+                }
+            }
+        }
+        self.slots[slot] = id.map(|own| Slot {
+            own,
+            active: None,
+            failing: None,
+        });
+        while matches!(self.slots.last(), Some(None)) {
+            self.slots.pop();
+        }
+        self.next_poll = f64::NEG_INFINITY;
+    }
+
+    /// The identifier of the open input of `slot`
+    #[must_use]
+    pub fn active(&self, slot: usize) -> Option<&B::Id> {
+        Some(&self.slots.get(slot)?.as_ref()?.active.as_ref()?.0)
+    }
+
+    /// Read the open input of `slot` with `read`, closing the input if that fails
+    pub fn read<T>(
+        &mut self,
+        slot: usize,
+        read: impl FnOnce(&B::Input) -> Result<Option<T>>,
+    ) -> Option<T> {
+        let state = self.slots.get_mut(slot)?.as_mut()?;
+        match read(&state.active.as_ref()?.1) {
+            Ok(value) => value,
+            Err(err) => {
+                state.close(slot, err.to_string(), &mut self.events);
+                None
+            }
+        }
+    }
+
+    /// Look for changed inputs at most every `POLL_SECS`, given the current time in seconds,
+    /// and reopen the inputs of slots accordingly
+    pub fn poll(&mut self, backend: &B, now: f64) {
+        if now < self.next_poll {
+            return;
+        }
+        self.next_poll = now + POLL_SECS;
+        let available = backend.devices();
+        for device in &available {
+            if !self.available.contains(device) {
+                self.events.push(Event::Connected(device.clone()));
+            }
+        }
+        for device in &self.available {
+            if !available.contains(device) {
+                self.events.push(Event::Disconnected(device.clone()));
+            }
+        }
+        self.available = available;
+        let available = |id: &B::Id| self.available.contains(&B::device(id));
+
+        // Not all backends report errors when devices are unplugged
+        for (idx, slot) in self.slots.iter_mut().enumerate() {
+            let Some(slot) = slot else { continue };
+            if matches!(&slot.active, Some((id, _)) if !available(id)) {
+                slot.close(idx, "Disconnected".to_owned(), &mut self.events);
+            }
+        }
+        // Switch to the own inputs which are available, taking them back from slots using
+        // them as fallback
+        for idx in 0..self.slots.len() {
+            let Some(own) = self.slots[idx]
+                .as_ref()
+                .filter(|slot| !slot.is_using(&slot.own))
+                .map(|slot| slot.own.clone())
+            else {
+                continue;
+            };
+            if !available(&own) {
+                continue;
+            }
+            for (other_idx, other) in self.slots.iter_mut().enumerate() {
+                if let Some(other) = other.as_mut().filter(|other| other.is_using(&own)) {
+                    other.close(other_idx, "Taken back".to_owned(), &mut self.events);
+                }
+            }
+            if let Some(slot) = &mut self.slots[idx] {
+                slot.open(backend, idx, &own, &mut self.events);
+            }
+        }
+        // Fill a slot without input with the fallback
+        let Some(fallback) = self.fallback.clone() else {
+            return;
+        };
+        if !available(&fallback)
+            || self
+                .slots
+                .iter()
+                .flatten()
+                .any(|slot| slot.is_using(&fallback))
+        {
+            return;
+        }
+        for (idx, slot) in self.slots.iter_mut().enumerate() {
+            if let Some(slot) = slot.as_mut().filter(|slot| slot.active.is_none()) {
+                if slot.open(backend, idx, &fallback, &mut self.events) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The events since the last call
+    pub fn take_events(&mut self) -> Vec<Event<B::Id, B::Device>> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Backend, Event, Supervisor};
+    use crate::platform::audio::players::PlayerInputs;
+    use anyhow::{anyhow, Result};
+    use std::cell::RefCell;
+
+    /// A host whose devices are plugged and broken by the test. Inputs are named after their
+    /// device, followed by a channel like `usb/1` for single channels.
+    #[derive(Default)]
+    struct FakeHost {
+        devices: RefCell<Vec<&'static str>>,
+        broken: RefCell<Vec<&'static str>>,
+    }
+    impl FakeHost {
+        fn plug(&self, device: &'static str) {
+            self.devices.borrow_mut().push(device);
+        }
+        fn unplug(&self, device: &'static str) {
+            self.devices.borrow_mut().retain(|other| *other != device);
+        }
+        fn is_plugged(&self, input: &'static str) -> bool {
+            self.devices.borrow().contains(&Self::device(&input))
+        }
+        /// Read an input, which fails if its device is gone
+        fn read(&self, input: &&'static str) -> Result<Option<&'static str>> {
+            if self.is_plugged(input) {
+                Ok(Some(*input))
+            } else {
+                Err(anyhow!("{} is gone", input))
+            }
+        }
+    }
+    impl Backend for FakeHost {
+        type Id = &'static str;
+        type Device = &'static str;
+        type Input = &'static str;
+
+        fn devices(&self) -> Vec<Self::Device> {
+            self.devices.borrow().clone()
+        }
+        fn device(id: &Self::Id) -> Self::Device {
+            id.split('/').next().unwrap_or_default()
+        }
+        fn open(&self, id: &Self::Id) -> Result<Self::Input> {
+            if self.broken.borrow().contains(id) || !self.is_plugged(id) {
+                Err(anyhow!("Failed to open {}", id))
+            } else {
+                Ok(*id)
+            }
+        }
+    }
+
+    /// Supervise `own` inputs of the slots with the default input as fallback
+    fn supervisor(host: &FakeHost, own: &[&'static str]) -> Supervisor<FakeHost> {
+        let mut inputs = PlayerInputs::default();
+        for (slot, id) in own.iter().enumerate() {
+            inputs.set(slot, Some(*id));
+        }
+        let available: Vec<_> = own
+            .iter()
+            .copied()
+            .chain(["default"])
+            .filter(|id| host.is_plugged(id))
+            .collect();
+        let resolved = inputs.resolve(&available, Some(&"default"));
+        Supervisor::new(host, &resolved, Some("default"))
+    }
+
+    #[test]
+    fn recover() {
+        let host = FakeHost::default();
+        host.plug("default");
+        host.plug("usb");
+        let mut supervisor = supervisor(&host, &["usb", "headset"]);
+        assert_eq!(supervisor.len(), 2);
+        assert_eq!(
+            supervisor.take_events(),
+            vec![
+                Event::Opened {
+                    slot: 0,
+                    input: "usb",
+                    fallback: false
+                },
+                Event::Opened {
+                    slot: 1,
+                    input: "default",
+                    fallback: true
+                },
+            ]
+        );
+        assert_eq!(supervisor.read(0, |input| host.read(input)), Some("usb"));
+
+        // The fallback is taken, so slot 0 stays without input
+        host.unplug("usb");
+        assert_eq!(supervisor.read(0, |input| host.read(input)), None);
+        supervisor.poll(&host, 1.);
+        assert_eq!(
+            supervisor.take_events(),
+            vec![
+                Event::Lost {
+                    slot: 0,
+                    input: "usb",
+                    reason: "usb is gone".to_owned()
+                },
+                Event::Disconnected("usb"),
+            ]
+        );
+        assert_eq!(supervisor.active(0), None);
+
+        // Slot 1 gets its own input back, so slot 0 may use the fallback
+        host.plug("headset");
+        supervisor.poll(&host, 1.5);
+        assert!(supervisor.take_events().is_empty());
+        supervisor.poll(&host, 2.);
+        assert_eq!(
+            supervisor.take_events(),
+            vec![
+                Event::Connected("headset"),
+                Event::Opened {
+                    slot: 1,
+                    input: "headset",
+                    fallback: false
+                },
+                Event::Opened {
+                    slot: 0,
+                    input: "default",
+                    fallback: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn failures() {
+        let host = FakeHost::default();
+        for id in ["default", "usb", "headset"] {
+            host.plug(id);
+        }
+        let mut supervisor = supervisor(&host, &["usb", "headset"]);
+        supervisor.take_events();
+
+        // Unplugged devices are noticed without reading them
+        host.unplug("usb");
+        supervisor.poll(&host, 0.);
+        assert_eq!(
+            supervisor.take_events(),
+            vec![
+                Event::Disconnected("usb"),
+                Event::Lost {
+                    slot: 0,
+                    input: "usb",
+                    reason: "Disconnected".to_owned()
+                },
+                Event::Opened {
+                    slot: 0,
+                    input: "default",
+                    fallback: true
+                },
+            ]
+        );
+
+        // Failures to open are reported once
+        host.plug("usb");
+        host.broken.borrow_mut().push("usb");
+        supervisor.poll(&host, 1.);
+        supervisor.poll(&host, 2.);
+        assert_eq!(
+            supervisor.take_events(),
+            vec![
+                Event::Connected("usb"),
+                Event::OpenFailed {
+                    slot: 0,
+                    input: "usb",
+                    reason: "Failed to open usb".to_owned()
+                },
+            ]
+        );
+        assert_eq!(supervisor.active(0), Some(&"default"));
+        host.broken.borrow_mut().clear();
+        supervisor.poll(&host, 3.);
+        assert_eq!(
+            supervisor.take_events(),
+            vec![Event::Opened {
+                slot: 0,
+                input: "usb",
+                fallback: false
+            }]
+        );
+        assert_eq!(supervisor.read(0, |input| host.read(input)), Some("usb"));
+    }
+
+    #[test]
+    fn assign() {
+        let host = FakeHost::default();
+        for id in ["default", "usb", "headset"] {
+            host.plug(id);
+        }
+        let mut supervisor = supervisor(&host, &["usb"]);
+        supervisor.take_events();
+
+        // Moving an input to another slot closes it first
+        supervisor.assign(2, Some("usb"));
+        supervisor.poll(&host, 0.5);
+        assert_eq!(
+            supervisor.take_events(),
+            vec![
+                Event::Lost {
+                    slot: 0,
+                    input: "usb",
+                    reason: "Reassigned".to_owned()
+                },
+                Event::Opened {
+                    slot: 2,
+                    input: "usb",
+                    fallback: false
+                },
+            ]
+        );
+        assert_eq!(supervisor.len(), 3);
+        assert_eq!(supervisor.active(0), None);
+
+        // Slots without input at the end are dropped
+        supervisor.assign(2, None);
+        assert_eq!(supervisor.len(), 0);
+    }
+
+    #[test]
+    fn channels() {
+        let host = FakeHost::default();
+        for id in ["default", "usb"] {
+            host.plug(id);
+        }
+        let mut supervisor = supervisor(&host, &["usb/1", "usb/2"]);
+        assert_eq!(supervisor.take_events().len(), 2);
+        // Channels stay open as long as their device is available
+        supervisor.poll(&host, 0.);
+        assert!(supervisor.take_events().is_empty());
+        assert_eq!(supervisor.active(1), Some(&"usb/2"));
+
+        host.unplug("usb");
+        supervisor.poll(&host, 1.);
+        let events = supervisor.take_events();
+        assert_eq!(events[0], Event::Disconnected("usb"));
+        assert_eq!(
+            events[2],
+            Event::Lost {
+                slot: 1,
+                input: "usb/2",
+                reason: "Disconnected".to_owned()
+            }
+        );
+    }
+}
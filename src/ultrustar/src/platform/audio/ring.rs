@@ -0,0 +1,287 @@
+//! Lock-free transfer of captured samples out of audio callbacks
+//!
+//! A single `Producer`, living in the audio callback, writes into a preallocated ring of samples
+//! which a single `Consumer` reads from. Neither side blocks or allocates. When the consumer
+//! lags behind, new samples are dropped and counted as overflows. Samples nobody reads may be
+//! skipped, which only counts them.
+//!
+//! `Window` keeps the most recent samples read from a `Consumer`, so analysis windows may span
+//! several callbacks and overlap each other.
+
+use std::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    Arc,
+};
+
+struct Shared {
+    /// Samples as bits of `f32`s
+    slots: Box<[AtomicU32]>,
+    /// Number of samples written, wrapping around
+    written: AtomicUsize,
+    /// Number of samples read, wrapping around
+    read: AtomicUsize,
+    /// Number of samples dropped because the ring was full
+    overflows: AtomicUsize,
+    /// Number of samples the producer skipped because nobody listened
+    skipped: AtomicUsize,
+}
+impl Shared {
+    fn slot(&self, count: usize) -> &AtomicU32 {
+        // The capacity is a power of two, so this is consistent when the counters wrap around
+        &self.slots[count & (self.slots.len() - 1)]
+    }
+}
+
+/// Create a ring for at least `capacity` samples
+#[must_use]
+pub fn ring(capacity: usize) -> (Producer, Consumer) {
+    let shared = Arc::new(Shared {
+        slots: (0..capacity.max(1).next_power_of_two())
+            .map(|_| AtomicU32::new(0))
+            .collect(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        overflows: AtomicUsize::new(0),
+        skipped: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+/// The writing end of a ring
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+impl Producer {
+    /// Append a sample, dropping it if the ring is full. Returns whether it was written.
+    pub fn push(&mut self, sample: f32) -> bool {
+        let shared = &*self.shared;
+        let written = shared.written.load(Ordering::Relaxed);
+        let read = shared.read.load(Ordering::Acquire);
+        if written.wrapping_sub(read) >= shared.slots.len() {
+            shared.overflows.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        shared
+            .slot(written)
+            .store(sample.to_bits(), Ordering::Relaxed);
+        shared
+            .written
+            .store(written.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Count `count` samples as captured without writing them, e.g. while nobody reads them
+    pub fn skip(&mut self, count: usize) {
+        self.shared.skipped.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// The reading end of a ring
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+impl Consumer {
+    /// Pass all samples written so far to `sink`. Returns their number.
+    pub fn pop_all(&mut self, mut sink: impl FnMut(f32)) -> usize {
+        let shared = &*self.shared;
+        let read = shared.read.load(Ordering::Relaxed);
+        let available = shared.written.load(Ordering::Acquire).wrapping_sub(read);
+        for offset in 0..available {
+            let bits = shared
+                .slot(read.wrapping_add(offset))
+                .load(Ordering::Relaxed);
+            sink(f32::from_bits(bits));
+        }
+        shared
+            .read
+            .store(read.wrapping_add(available), Ordering::Release);
+        available
+    }
+
+    /// Number of samples dropped so far
+    #[must_use]
+    pub fn overflows(&self) -> usize {
+        self.shared.overflows.load(Ordering::Relaxed)
+    }
+
+    /// Number of samples skipped so far
+    #[must_use]
+    pub fn skipped(&self) -> usize {
+        self.shared.skipped.load(Ordering::Relaxed)
+    }
+}
+
+/// The most recent samples of a `Consumer`
+pub struct Window {
+    consumer: Consumer,
+    len: usize,
+    samples: Vec<f32>,
+    /// Number of samples captured up to the end of `samples`, including dropped and skipped ones
+    end: u64,
+    /// Overflows already read from the consumer
+    overflows: usize,
+    /// Samples dropped after the end of `samples`, counted in `end` once later samples are read
+    gap: u64,
+    /// Skipped samples already counted in `end`
+    skipped: usize,
+}
+impl Window {
+    /// Keep the last `len` samples read from `consumer`
+    #[must_use]
+    pub fn new(consumer: Consumer, len: usize) -> Self {
+        Self {
+            consumer,
+            len,
+            samples: Vec::with_capacity(2 * len),
+            end: 0,
+            overflows: 0,
+            gap: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Read the samples captured since the last update. Returns their number.
+    pub fn update(&mut self) -> usize {
+        // Samples are only dropped while the ring is full, so those dropped so far were
+        // captured after all samples available now
+        let overflows = self.consumer.overflows();
+        let dropped = overflows.wrapping_sub(self.overflows);
+        self.overflows = overflows;
+        let samples = &mut self.samples;
+        let previous = samples.len();
+        let count = self.consumer.pop_all(|sample| samples.push(sample));
+        if count > 0 {
+            if self.gap > 0 {
+                // Samples before a gap don't continue with the new ones
+                samples.drain(..previous);
+            }
+            self.end += self.gap + count as u64;
+            self.gap = 0;
+        }
+        if samples.len() > self.len {
+            samples.drain(..samples.len() - self.len);
+        }
+        self.gap += dropped as u64;
+        // Samples are only skipped while the window isn't read, and it's cleared before it's
+        // read again
+        let skipped = self.consumer.skipped();
+        self.end += skipped.wrapping_sub(self.skipped) as u64;
+        self.skipped = skipped;
+        count
+    }
+
+    /// Forget the samples read so far, e.g. to skip those captured while nobody was listening
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Up to `len` of the most recent samples
+    #[must_use]
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Number of samples captured up to the end of `samples`, including dropped and skipped
+    /// ones
+    #[must_use]
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Number of samples dropped because they weren't read in time
+    #[must_use]
+    pub fn overflows(&self) -> usize {
+        self.overflows
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ring, Window};
+
+    #[test]
+    fn overflow() {
+        let (mut producer, mut consumer) = ring(3);
+        for sample in 0..6_u8 {
+            producer.push(f32::from(sample));
+        }
+        assert_eq!(consumer.overflows(), 2);
+        let mut read = Vec::new();
+        assert_eq!(consumer.pop_all(|sample| read.push(sample)), 4);
+        assert_eq!(read, vec![0., 1., 2., 3.]);
+        assert_eq!(consumer.pop_all(|_| ()), 0);
+        // Wrap around
+        for sample in 6..9_u8 {
+            assert!(producer.push(f32::from(sample)));
+        }
+        read.clear();
+        consumer.pop_all(|sample| read.push(sample));
+        assert_eq!(read, vec![6., 7., 8.]);
+    }
+
+    #[test]
+    fn window() {
+        let (mut producer, consumer) = ring(8);
+        let mut window = Window::new(consumer, 4);
+        for sample in 0..3_u8 {
+            producer.push(f32::from(sample));
+        }
+        assert_eq!(window.update(), 3);
+        assert_eq!(window.samples(), &[0., 1., 2.]);
+        // Windows span the writes of several callbacks
+        for sample in 3..6_u8 {
+            producer.push(f32::from(sample));
+        }
+        assert_eq!(window.update(), 3);
+        assert_eq!(window.samples(), &[2., 3., 4., 5.]);
+        assert_eq!(window.end(), 6);
+        for sample in 6..16_u8 {
+            producer.push(f32::from(sample));
+        }
+        assert_eq!(window.update(), 8);
+        assert_eq!(window.overflows(), 2);
+        assert_eq!(window.end(), 14);
+        assert_eq!(window.samples(), &[10., 11., 12., 13.]);
+        // 14 and 15 were dropped
+        for sample in 16..18_u8 {
+            producer.push(f32::from(sample));
+        }
+        assert_eq!(window.update(), 2);
+        assert_eq!(window.end(), 18);
+        assert_eq!(window.samples(), &[16., 17.]);
+        window.clear();
+        assert!(window.samples().is_empty());
+        assert_eq!(window.end(), 18);
+        // Skipped samples only count
+        producer.skip(5);
+        producer.push(23.);
+        assert_eq!(window.update(), 1);
+        assert_eq!(window.end(), 24);
+        assert_eq!(window.samples(), &[23.]);
+    }
+
+    #[test]
+    fn threads() {
+        let (mut producer, mut consumer) = ring(64);
+        let writer = std::thread::spawn(move || {
+            for sample in 0..10_000_u16 {
+                while !producer.push(f32::from(sample)) {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0_u16;
+        while expected < 10_000 {
+            consumer.pop_all(|sample| {
+                assert!((sample - f32::from(expected)).abs() < f32::EPSILON);
+                expected += 1;
+            });
+        }
+        writer.join().unwrap();
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{gfx::Renderer, platform::PlatformApi, Event, EventLoop, Signals, UserData};
+use crate::{
+    gfx::Renderer, model::highscores::Highscores, platform::PlatformApi, Event, EventLoop, Signals,
+    UserData,
+};
 use anyhow::anyhow;
 use directories::ProjectDirs;
 use glutin::{Api, GlRequest};
@@ -17,14 +20,20 @@ pub struct Settings;
 pub struct Platform {
     event_loop: EventLoop,
 }
+fn get_project_dirs() -> Result<ProjectDirs, anyhow::Error> {
+    ProjectDirs::from("io.github", "suluke", "ultrustar")
+        .ok_or_else(|| anyhow!("Failed to retrieve application directories"))
+}
 fn get_userdata_path(user_id: &str) -> Result<PathBuf, anyhow::Error> {
-    let proj_dirs = ProjectDirs::from("io.github", "suluke", "ultrustar")
-        .ok_or_else(|| anyhow!("Failed to retrieve application directories"))?;
-
-    let mut dest = proj_dirs.config_dir().to_owned();
+    let mut dest = get_project_dirs()?.config_dir().to_owned();
     dest.push(format!("{}_user.json", user_id));
     Ok(dest)
 }
+fn get_highscores_path() -> Result<PathBuf, anyhow::Error> {
+    let mut dest = get_project_dirs()?.data_dir().to_owned();
+    dest.push("highscores.json");
+    Ok(dest)
+}
 impl PlatformApi for Platform {
     type Settings = Settings;
 
@@ -71,6 +80,25 @@ impl PlatformApi for Platform {
         Ok(())
     }
 
+    fn load_highscores() -> Result<Highscores, anyhow::Error> {
+        match File::open(get_highscores_path()?) {
+            Ok(src) => serde_json::from_reader(src).map_err(anyhow::Error::from),
+            Err(err) if matches!(err.kind(), std::io::ErrorKind::NotFound) => {
+                Ok(Highscores::default())
+            }
+            Err(err) => Err(anyhow::Error::from(err)),
+        }
+    }
+
+    fn persist_highscores(highscores: &Highscores) -> Result<(), anyhow::Error> {
+        let dest = get_highscores_path()?;
+        if let Some(dir) = dest.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        serde_json::to_writer(File::create(dest)?, highscores)?;
+        Ok(())
+    }
+
     fn init(_settings: Self::Settings) -> Result<Self, Self::InitError> {
         let event_loop = EventLoop::with_user_event();
 
@@ -1,5 +1,5 @@
 use super::PlatformApi;
-use crate::{gfx::Renderer, Event, EventLoop, Signals};
+use crate::{gfx::Renderer, model::highscores::Highscores, Event, EventLoop, Signals};
 use anyhow::anyhow;
 use js_sys::{Boolean, JsString, Map, Object as JsObject};
 use log::info;
@@ -75,6 +75,9 @@ fn create_canvas() -> Result<HtmlCanvasElement, JsValue> {
     Ok(canvas)
 }
 
+/// Local storage key of the highscores of all users
+const HIGHSCORES_KEY: &str = "highscores";
+
 fn local_storage() -> Result<Storage, ErrorFromJs> {
     Ok(window()
         .local_storage()
@@ -199,6 +202,19 @@ impl PlatformApi for Platform {
             .map_js_error()?;
         Ok(())
     }
+    fn load_highscores() -> Result<Highscores, anyhow::Error> {
+        let storage = local_storage()?;
+        match storage.get_item(HIGHSCORES_KEY).map_js_error()? {
+            Some(data) => Ok(serde_json::from_str(&data)?),
+            None => Ok(Highscores::default()),
+        }
+    }
+    fn persist_highscores(highscores: &Highscores) -> Result<(), anyhow::Error> {
+        let storage = local_storage()?;
+        let json = serde_json::to_string(highscores)?;
+        storage.set_item(HIGHSCORES_KEY, &json).map_js_error()?;
+        Ok(())
+    }
 
     fn create_gl_window(&self) -> Result<Self::GlWindow, anyhow::Error> {
         let leme = self.borrow();
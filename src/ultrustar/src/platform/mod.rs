@@ -42,6 +42,22 @@ pub trait PlatformApi: Sized {
     /// In case I/O failed
     fn persist_userdata(data: &crate::UserData) -> Result<(), anyhow::Error>;
 
+    /// Load the highscores of all users from persistent storage
+    ///
+    /// # Errors
+    ///
+    /// In case existing highscores cannot be read
+    fn load_highscores() -> Result<crate::model::highscores::Highscores, anyhow::Error>;
+
+    /// Store the highscores of all users to persistent storage
+    ///
+    /// # Errors
+    ///
+    /// In case I/O failed
+    fn persist_highscores(
+        highscores: &crate::model::highscores::Highscores,
+    ) -> Result<(), anyhow::Error>;
+
     /// Initializes (instantiates) the platform
     ///
     /// # Errors
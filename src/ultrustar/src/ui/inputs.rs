@@ -0,0 +1,93 @@
+//! Choosing the note input and the scoring options of each player slot
+
+use super::Action;
+use crate::model::scoring::{Difficulty, PlayerOptions};
+
+/// Difficulties to choose from. Custom tolerances are kept, but cannot be chosen here.
+const DIFFICULTIES: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard];
+
+/// Name of `difficulty` for display
+pub(super) fn difficulty_name(difficulty: Difficulty) -> &'static str {
+    match difficulty {
+        Difficulty::Easy => "Easy",
+        Difficulty::Medium => "Medium",
+        Difficulty::Hard => "Hard",
+        Difficulty::Custom(_) => "Custom",
+    }
+}
+
+/// The inputs of the player slots, by their labels, and their options
+pub struct Inputs {
+    /// The inputs to choose from
+    pub available: Vec<String>,
+    /// The input chosen for each slot
+    pub chosen: Vec<Option<String>>,
+    /// The input each slot actually uses, e.g. the fallback while its own input is missing
+    pub active: Vec<Option<String>>,
+    /// The scoring options of each slot
+    pub options: Vec<PlayerOptions>,
+}
+
+/// Show the input and options of each player slot, with a slot more to add a player
+pub fn show(ui: &mut egui::Ui, inputs: &Inputs) -> Option<Action> {
+    ui.heading("Players");
+    let mut action = None;
+    egui::Grid::new("player_inputs").show(ui, |ui| {
+        for slot in 0..=inputs.chosen.len() {
+            let chosen = inputs.chosen.get(slot).cloned().flatten();
+            let active = inputs.active.get(slot).cloned().flatten();
+            let options = inputs.options.get(slot).cloned().unwrap_or_default();
+            ui.label(format!("Player {}", slot + 1));
+            egui::ComboBox::from_id_source(slot)
+                .selected_text(chosen.clone().unwrap_or_else(|| "None".to_owned()))
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(chosen.is_none(), "None").clicked() {
+                        action = Some(Action::SetInput(slot, None));
+                    }
+                    for (idx, label) in inputs.available.iter().enumerate() {
+                        if ui
+                            .selectable_label(chosen.as_ref() == Some(label), label.as_str())
+                            .clicked()
+                        {
+                            action = Some(Action::SetInput(slot, Some(idx)));
+                        }
+                    }
+                });
+            match active {
+                Some(active) if Some(&active) != chosen.as_ref() => {
+                    ui.label(format!("Using {}", active));
+                }
+                None if chosen.is_some() => {
+                    ui.label("Not available");
+                }
+                _ => {
+                    ui.label("");
+                }
+            }
+            egui::ComboBox::from_id_source(("difficulty", slot))
+                .selected_text(difficulty_name(options.difficulty))
+                .show_ui(ui, |ui| {
+                    for difficulty in DIFFICULTIES {
+                        if ui
+                            .selectable_label(
+                                options.difficulty == difficulty,
+                                difficulty_name(difficulty),
+                            )
+                            .clicked()
+                        {
+                            action = Some(Action::SetDifficulty(slot, difficulty));
+                        }
+                    }
+                });
+            let mut octave_independent = options.octave_independent;
+            if ui.checkbox(&mut octave_independent, "Any octave").changed() {
+                action = Some(Action::SetOctaveIndependent(slot, octave_independent));
+            }
+            ui.end_row();
+        }
+    });
+    if ui.button("Back").clicked() {
+        action = Some(Action::Stop);
+    }
+    action
+}
@@ -0,0 +1,33 @@
+//! Lyrics display with the kind of each note
+
+use crate::model::{lyrics::LyricCursor, NoteKind};
+use egui::Color32;
+
+const GOLDEN: Color32 = Color32::from_rgb(230, 180, 30);
+const RAP: Color32 = Color32::from_rgb(90, 170, 230);
+const GOLDEN_RAP: Color32 = Color32::from_rgb(230, 120, 40);
+const FREESTYLE: Color32 = Color32::GRAY;
+const SUNG: Color32 = Color32::from_rgb(80, 200, 120);
+
+/// Show the line of `cursor`, coloring syllables by note kind and highlighting the ones sung
+pub fn show(ui: &mut egui::Ui, cursor: &LyricCursor<'_>) {
+    ui.horizontal(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.;
+        for (idx, syllable) in cursor.line.iter().enumerate() {
+            let color = match syllable.kind {
+                _ if cursor.current.is_some_and(|current| idx <= current) => SUNG,
+                NoteKind::Normal => ui.visuals().text_color(),
+                NoteKind::Golden => GOLDEN,
+                NoteKind::Rap => RAP,
+                NoteKind::GoldenRap => GOLDEN_RAP,
+                NoteKind::Freestyle => FREESTYLE,
+            };
+            let text = egui::RichText::new(syllable.text).color(color);
+            ui.label(if syllable.kind == NoteKind::Freestyle {
+                text.italics()
+            } else {
+                text
+            });
+        }
+    });
+}
@@ -0,0 +1,41 @@
+//! Diagnostic metrics for the score screen and training mode
+
+use crate::model::scoring::metrics::Metrics;
+
+/// Show the averages of `metrics` as a table
+pub fn show(ui: &mut egui::Ui, metrics: &Metrics) {
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| "–".to_owned());
+    egui::Grid::new("performance_metrics").show(ui, |ui| {
+        ui.label("Deviation");
+        ui.label(or_dash(
+            metrics
+                .mean_cents()
+                .map(|cents| format!("{:.0} cents", cents)),
+        ));
+        ui.end_row();
+        ui.label("Stability");
+        ui.label(or_dash(
+            metrics
+                .stability()
+                .map(|cents| format!("±{:.0} cents", cents)),
+        ));
+        ui.end_row();
+        ui.label("Vibrato");
+        ui.label(or_dash(metrics.vibrato().map(|vibrato| {
+            format!(
+                "{:.1} Hz, ±{:.0} cents",
+                vibrato.rate_hz, vibrato.depth_cents
+            )
+        })));
+        ui.end_row();
+        ui.label("Timing");
+        ui.label(or_dash(metrics.onset_bias_ms().map(|ms| {
+            if ms < 0. {
+                format!("{:.0} ms early", -ms)
+            } else {
+                format!("{:.0} ms late", ms)
+            }
+        })));
+        ui.end_row();
+    });
+}
@@ -1,5 +1,21 @@
+mod inputs;
+mod lyrics;
+mod metrics;
+mod results;
+mod singing;
+mod songs;
+pub use inputs::Inputs;
+pub use lyrics::show as show_lyrics;
+pub use metrics::show as show_metrics;
+
 use crate::{
     gfx::Renderer as RendererApi,
+    model::{
+        performance::Performance,
+        scoring::{metrics::Metrics, Difficulty},
+        timing::Offsets,
+        Library, Score,
+    },
     platform::{Platform, PlatformApi},
 };
 use egui_winit::State as EventAccumulator;
@@ -20,6 +36,39 @@ impl MainUISettings {
     }
 }
 
+/// What the main UI shows
+pub enum Screen<'a> {
+    /// The library to choose a song from
+    Songs(&'a Library),
+    /// A performance at the given time on the audio timeline
+    Singing(&'a Performance, f64),
+    /// The score and metrics of each player slot after a song
+    Results(&'a [(Score, Metrics)]),
+    /// The note inputs and options of the player slots
+    Inputs(Inputs),
+}
+
+/// What the user chose to do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Sing the song at this index of the library
+    Sing(usize),
+    /// Choose the note inputs of the player slots
+    EditInputs,
+    /// Give a player slot the input at this index of the available ones, or none
+    SetInput(usize, Option<usize>),
+    /// Change how precisely a player slot has to sing
+    SetDifficulty(usize, Difficulty),
+    /// Compare the pitch of a player slot regardless of the octave, or not
+    SetOctaveIndependent(usize, bool),
+    /// Hide the warnings shown so far
+    DismissWarnings,
+    /// Correct the timing of the song being sung
+    SetOffsets(Offsets),
+    /// Stop singing or leave the results or inputs, back to the songs
+    Stop,
+}
+
 pub struct MainUI {
     events: EventAccumulator,
     ctx: egui::CtxRef,
@@ -44,23 +93,46 @@ impl MainUI {
     pub fn push_event(&mut self, event: &WindowEvent) {
         self.events.on_event(&self.ctx, event);
     }
-    fn build(ctx: &egui::CtxRef) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.add(egui::Label::new("Hello World!"));
-            ui.label("A shorter and more convenient way to add a label.");
-            if ui.button("Click me").clicked() {
-                // take some action here
-            }
-        });
+    fn build(ctx: &egui::CtxRef, screen: &Screen<'_>, warnings: &[String]) -> Option<Action> {
+        let mut dismissed = false;
+        if !warnings.is_empty() {
+            egui::TopBottomPanel::top("warnings").show(ctx, |ui| {
+                for warning in warnings {
+                    ui.colored_label(egui::Color32::from_rgb(230, 120, 40), warning.as_str());
+                }
+                dismissed = ui.button("Dismiss").clicked();
+            });
+        }
+        let action = egui::CentralPanel::default()
+            .show(ctx, |ui| match screen {
+                Screen::Songs(library) => songs::show(ui, library),
+                Screen::Singing(performance, now) => singing::show(ui, performance, *now),
+                Screen::Results(results) => results::show(ui, results),
+                Screen::Inputs(inputs) => inputs::show(ui, inputs),
+            })
+            .inner;
+        if dismissed {
+            Some(Action::DismissWarnings)
+        } else {
+            action
+        }
     }
-    // FIXME not fully implemented
-    #[allow(clippy::unused_self)]
-    pub fn render(&mut self, renderer: &Renderer) {
+    /// Render `screen` below the `warnings` for the user, returning what the user chose to do
+    pub fn render(
+        &mut self,
+        renderer: &Renderer,
+        screen: &Screen<'_>,
+        warnings: &[String],
+    ) -> Option<Action> {
         let window = renderer.get_window();
         let raw_input: egui::RawInput = self.events.take_egui_input(window);
-        let (output, shapes) = self.ctx.run(raw_input, Self::build);
+        let mut action = None;
+        let (output, shapes) = self
+            .ctx
+            .run(raw_input, |ctx| action = Self::build(ctx, screen, warnings));
         let meshes = self.ctx.tessellate(shapes);
         self.events.handle_output(window, &self.ctx, output);
         renderer.render(meshes);
+        action
     }
 }
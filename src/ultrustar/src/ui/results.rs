@@ -0,0 +1,20 @@
+//! The score screen after a song
+
+use super::{inputs::difficulty_name, metrics, singing::octaves_text, Action};
+use crate::model::{scoring::metrics::Metrics, Score};
+
+/// Show the score and metrics of each player slot
+pub fn show(ui: &mut egui::Ui, results: &[(Score, Metrics)]) -> Option<Action> {
+    ui.label("All scores are kept in the highscores, only Player 1's in your statistics");
+    for (slot, (score, player_metrics)) in results.iter().enumerate() {
+        ui.heading(format!("Player {}: {}", slot + 1, score.total()));
+        ui.label(format!("Difficulty: {}", difficulty_name(score.difficulty)));
+        if let Some(offset) = score.octave_offset {
+            ui.label(format!("Sung {}", octaves_text(offset)));
+        }
+        ui.label(format!("Longest combo: {}", score.max_combo()));
+        ui.push_id(slot, |ui| metrics::show(ui, player_metrics));
+        ui.separator();
+    }
+    ui.button("Continue").clicked().then_some(Action::Stop)
+}
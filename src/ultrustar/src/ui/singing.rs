@@ -0,0 +1,48 @@
+//! The lyrics of each player while singing
+
+use super::{lyrics, Action};
+use crate::model::performance::Performance;
+
+/// Describe where `offset` octaves are relative to the written notes
+pub(super) fn octaves_text(offset: i32) -> String {
+    let octaves = if offset.abs() == 1 {
+        "octave"
+    } else {
+        "octaves"
+    };
+    match offset {
+        0 => "in the written octave".to_owned(),
+        _ if offset > 0 => format!("{} {} above", offset, octaves),
+        _ => format!("{} {} below", -offset, octaves),
+    }
+}
+
+/// Show the lyrics of each player slot at `now`, with controls to correct the timing of the song
+pub fn show(ui: &mut egui::Ui, performance: &Performance, now: f64) -> Option<Action> {
+    for slot in 0..performance.players() {
+        let mut text = format!("Player {}: {}", slot + 1, performance.score(slot));
+        if let Some(offset) = performance.octave_offset(slot) {
+            text.push_str(&format!(" ({})", octaves_text(offset)));
+        }
+        ui.label(text);
+        if let Some(cursor) = performance.lyric_cursor(slot, now) {
+            lyrics::show(ui, &cursor);
+        }
+    }
+    ui.separator();
+    let mut offsets = performance.offsets();
+    let changed = ui
+        .horizontal(|ui| {
+            ui.label("Notes delay");
+            let gap = ui.add(egui::DragValue::new(&mut offsets.gap_ms).suffix(" ms"));
+            ui.label("Lyrics delay");
+            let lyrics = ui.add(egui::DragValue::new(&mut offsets.lyrics_delay_ms).suffix(" ms"));
+            gap.changed() || lyrics.changed()
+        })
+        .inner;
+    if ui.button("Stop").clicked() {
+        Some(Action::Stop)
+    } else {
+        changed.then_some(Action::SetOffsets(offsets))
+    }
+}
@@ -0,0 +1,21 @@
+//! Choosing a song from the library
+
+use super::Action;
+use crate::model::Library;
+
+/// List the songs of `library`, returning the one clicked
+pub fn show(ui: &mut egui::Ui, library: &Library) -> Option<Action> {
+    ui.heading("Songs");
+    let mut action = None;
+    if ui.button("Players").clicked() {
+        action = Some(Action::EditInputs);
+    }
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for (idx, song) in library.iter().enumerate() {
+            if ui.button(song.name()).clicked() {
+                action = Some(Action::Sing(idx));
+            }
+        }
+    });
+    action
+}